@@ -0,0 +1,183 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    match_::{Ball, Paddle, PaddleInput, Player, Velocity},
+    BALL_RADIUS, BOTTOM_WALL, PADDLE_SIZE, PADDLE_SPEED, TOP_WALL, WALL_THICKNESS,
+};
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum AiDifficulty {
+    Easy,
+    Normal,
+    Hard,
+    Perfect,
+}
+
+impl AiDifficulty {
+    /// Seconds the AI takes to react after the ball changes direction
+    fn reaction_delay(&self) -> f32 {
+        match self {
+            AiDifficulty::Easy => 0.35,
+            AiDifficulty::Normal => 0.2,
+            AiDifficulty::Hard => 0.1,
+            AiDifficulty::Perfect => 0.,
+        }
+    }
+
+    /// Max error in pixels added to the predicted intercept
+    fn prediction_noise(&self) -> f32 {
+        match self {
+            AiDifficulty::Easy => 90.,
+            AiDifficulty::Normal => 45.,
+            AiDifficulty::Hard => 15.,
+            AiDifficulty::Perfect => 0.,
+        }
+    }
+
+    /// Max paddle speed relative to PADDLE_SPEED
+    fn speed_factor(&self) -> f32 {
+        match self {
+            AiDifficulty::Easy => 0.6,
+            AiDifficulty::Normal => 0.8,
+            AiDifficulty::Hard => 0.95,
+            AiDifficulty::Perfect => 1.,
+        }
+    }
+
+    /// Whether the AI hits the ball off-center on purpose to angle its returns
+    fn aims(&self) -> bool {
+        matches!(self, AiDifficulty::Hard | AiDifficulty::Perfect)
+    }
+}
+
+/// Marks a paddle as computer-controlled
+#[derive(Component)]
+pub struct Ai {
+    pub difficulty: AiDifficulty,
+    reaction: Timer,
+    // direction the ball was heading on the last tick
+    heading: f32,
+    // noise and aim offset applied to the current shot
+    offset: f32,
+    target_y: f32,
+    rng: fastrand::Rng,
+}
+
+impl Ai {
+    /// AI steering `player`'s paddle, its aim errors drawn from the match seed so seeded
+    /// matches play out the same
+    pub fn new(difficulty: AiDifficulty, match_seed: u64, player: Player) -> Ai {
+        Ai {
+            difficulty,
+            reaction: Timer::from_seconds(difficulty.reaction_delay(), TimerMode::Once),
+            heading: 0.,
+            offset: 0.,
+            target_y: 0.,
+            // each paddle draws from its own stream
+            rng: fastrand::Rng::with_seed(
+                match_seed ^ (player as u64 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15),
+            ),
+        }
+    }
+}
+
+/// Y position of the ball center once it reaches `target_x`, accounting for bounces off the
+/// top and bottom walls. None if the ball is not travelling towards `target_x`.
+pub fn predict_intercept(position: Vec2, velocity: Vec2, target_x: f32) -> Option<f32> {
+    let dx = target_x - position.x;
+    if velocity.x == 0. || dx.signum() != velocity.x.signum() {
+        return None;
+    }
+    let t = dx / velocity.x;
+
+    // range the ball center can travel in before touching a wall
+    let top = TOP_WALL - WALL_THICKNESS / 2. - BALL_RADIUS;
+    let bottom = BOTTOM_WALL + WALL_THICKNESS / 2. + BALL_RADIUS;
+    let height = top - bottom;
+
+    // unfold the bounces: in a mirrored arena the ball travels in a straight line
+    let y = (position.y + velocity.y * t - bottom).rem_euclid(2. * height);
+    if y > height {
+        Some(bottom + 2. * height - y)
+    } else {
+        Some(bottom + y)
+    }
+}
+
+pub fn move_ai_paddle(
+    ball_query: Query<(&Transform, &Velocity), With<Ball>>,
+    mut paddle_query: Query<(&Transform, &Player, &mut PaddleInput, &mut Ai), With<Paddle>>,
+    opponent_query: Query<(&Transform, &Player), With<Paddle>>,
+    time: Res<Time>,
+) {
+    let balls: Vec<(Vec2, Vec2)> = ball_query
+        .iter()
+        .map(|(transform, velocity)| (transform.translation.truncate(), velocity.0))
+        .collect();
+    let Some(&first_ball) = balls.first() else {
+        return;
+    };
+
+    for (transform, player, mut input, mut ai) in &mut paddle_query {
+        let difficulty = ai.difficulty;
+
+        // x where the ball touches the face of the paddle, which faces the center of the arena
+        let face_x = transform.translation.x
+            - transform.translation.x.signum() * (PADDLE_SIZE.x / 2. + BALL_RADIUS);
+
+        // with extra balls in play, go for the one that gets to the paddle first
+        let time_to_face =
+            |&(position, velocity): &(Vec2, Vec2)| (face_x - position.x) / velocity.x;
+        let (ball_position, ball_velocity) = balls
+            .iter()
+            .filter(|&&(position, velocity)| {
+                predict_intercept(position, velocity, face_x).is_some()
+            })
+            .min_by(|a, b| time_to_face(a).total_cmp(&time_to_face(b)))
+            .copied()
+            .unwrap_or(first_ball);
+
+        // the ball was served or returned: take a moment before reading the new shot
+        let heading = ball_velocity.x.signum();
+        if heading != ai.heading {
+            ai.heading = heading;
+            ai.reaction.reset();
+        }
+
+        if ai.reaction.tick(time.delta()).just_finished() {
+            let noise = (ai.rng.f32() * 2. - 1.) * difficulty.prediction_noise();
+
+            // hit off the centre of the paddle to send the ball away from the opponent, see
+            // the impact offset move_ball hands to match_::deflect
+            let aim = if difficulty.aims() {
+                let opponent = opponent_query
+                    .iter()
+                    .find(|(_, opponent)| *opponent != player);
+                let away = match opponent {
+                    Some((opponent, _)) => -opponent.translation.y.signum(),
+                    None if ai.rng.bool() => 1.,
+                    None => -1.,
+                };
+                -away * 0.6 * PADDLE_SIZE.y / 2.
+            } else {
+                0.
+            };
+
+            ai.offset = noise + aim;
+        }
+
+        if ai.reaction.finished() {
+            // wait in the middle while the ball is heading to the opponent
+            ai.target_y = match predict_intercept(ball_position, ball_velocity, face_x) {
+                Some(y) => y + ai.offset,
+                None => 0.,
+            };
+        }
+
+        // full speed until the paddle would overshoot the target this tick
+        let full_step = PADDLE_SPEED * time.delta_seconds();
+        let speed = difficulty.speed_factor();
+        input.0 = ((ai.target_y - transform.translation.y) / full_step).clamp(-speed, speed);
+    }
+}
@@ -4,17 +4,24 @@ use bevy::prelude::*;
 
 use crate::{
     despawn_screen,
-    match_::{rand_ball_dir, Ball, Paddle, Player, RoundState, Velocity},
-    BALL_START_POSITION, BALL_START_SPEED, BALL_START_VELOCITY, PADDLE_A_START_POSITION,
-    PADDLE_B_START_POSITION, TEXT_COLOR,
+    doubles::{seat_of, Seat},
+    input_map::{Action, InputMap},
+    match_::{
+        match_running, serve_velocity, Ball, InGoal, KeyboardControl, Paddle, Player, RoundState,
+        ServeRng, Spin, Velocity,
+    },
+    rules::MatchRules,
+    MatchInfo, MatchMode, BALL_START_POSITION, TEXT_COLOR,
 };
 
 pub fn countdown_plugin(app: &mut App) {
     app.add_systems(
         Update,
-        run_countdown.run_if(in_state(RoundState::Countdown)),
+        (run_countdown, serve_on_key)
+            .run_if(in_state(RoundState::Countdown).and_then(match_running)),
     )
     .add_systems(OnEnter(RoundState::Countdown), setup_countdown)
+    .add_systems(OnEnter(RoundState::In), serve_ball)
     .add_systems(
         OnExit(RoundState::Countdown),
         move |to_despawn: Query<Entity, With<OnCountdownScreen>>, cmd: Commands| {
@@ -34,23 +41,36 @@ pub struct CountdownTimedMessage {
 }
 
 pub fn setup_countdown(
-    mut q_ball: Query<(&mut Transform, &mut Velocity), (With<Ball>, Without<Paddle>)>,
-    mut q_paddle: Query<(&mut Transform, &Player), With<Paddle>>,
+    mut q_ball: Query<
+        (Entity, &mut Transform, &mut Velocity, &mut Spin),
+        (With<Ball>, Without<Paddle>),
+    >,
+    mut q_paddle: Query<(&mut Transform, &Player, Option<&Seat>), With<Paddle>>,
+    mut match_: ResMut<MatchInfo>,
+    rules: Res<MatchRules>,
+    mut serve_rng: ResMut<ServeRng>,
+    match_mode: Res<MatchMode>,
     mut commands: Commands,
 ) {
-    let (mut ball_transform, mut ball_velocity) = q_ball.single_mut();
-    ball_transform.translation = BALL_START_POSITION;
-    *ball_velocity = Velocity(BALL_START_VELOCITY);
-
-    for (mut paddle_transform, player) in q_paddle.iter_mut() {
-        match player {
-            Player::A => {
-                paddle_transform.translation = PADDLE_A_START_POSITION;
-            }
-            Player::B => {
-                paddle_transform.translation = PADDLE_B_START_POSITION;
-            }
-        }
+    // every point starts with one ball, the extra balls of the last point are cleared away
+    let mut balls = q_ball.iter_mut();
+    if let Some((ball, mut ball_transform, mut ball_velocity, mut spin)) = balls.next() {
+        commands.entity(ball).remove::<InGoal>();
+        ball_transform.translation = BALL_START_POSITION;
+        *ball_velocity = Velocity(Vec2::ZERO);
+        *spin = Spin(0.);
+    }
+    for (ball, ..) in balls {
+        commands.entity(ball).despawn();
+    }
+
+    // network clients are told the serve by the host
+    if match_mode.is_authority() {
+        match_.serve = serve_velocity(&mut serve_rng.0, match_.server, rules.serve_cone);
+    }
+
+    for (mut paddle_transform, player, seat) in q_paddle.iter_mut() {
+        paddle_transform.translation = seat.map_or(player.start_position(), Seat::start_position);
     }
 
     let texts: [String; 4] = [
@@ -121,11 +141,41 @@ pub fn setup_countdown(
     });
 }
 
+pub fn serve_ball(mut q_ball: Query<&mut Velocity, With<Ball>>, match_: Res<MatchInfo>) {
+    for mut velocity in &mut q_ball {
+        velocity.0 = match_.serve;
+    }
+}
+
+/// Lets the server serve before the countdown ends by pressing their serve key
+pub fn serve_on_key(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    input_map: Res<InputMap>,
+    q_keyboard: Query<(&Player, Option<&Seat>), With<KeyboardControl>>,
+    match_: Res<MatchInfo>,
+    match_mode: Res<MatchMode>,
+    mut next_state: ResMut<NextState<RoundState>>,
+) {
+    // network clients follow the host into the round
+    if !match_mode.is_authority() {
+        return;
+    }
+    // either player of a doubles team serves with their own key
+    let server = match_.server;
+    if q_keyboard.iter().any(|(&player, seat)| {
+        player == server
+            && keyboard_input.just_pressed(input_map.key(seat_of(player, seat), Action::Serve))
+    }) {
+        next_state.set(RoundState::In);
+    }
+}
+
 pub fn run_countdown(
     mut q_countdown_text: Query<&mut Text, With<OnCountdownScreen>>,
     mut q_countdown_tmsg: Query<&mut CountdownTimedMessage>,
     time: Res<Time>,
     mut next_state: ResMut<NextState<RoundState>>,
+    match_mode: Res<MatchMode>,
 ) {
     let mut countdowner = q_countdown_tmsg.single_mut();
 
@@ -135,6 +185,11 @@ pub fn run_countdown(
         info!("countdowner finished!");
         countdowner.cursor += 1;
         if countdowner.cursor == 3 {
+            if !match_mode.is_authority() {
+                // network clients hold the last count until the host starts the round
+                countdowner.cursor = 2;
+                return;
+            }
             next_state.set(RoundState::In);
             countdowner.cursor = 0;
             return;
@@ -0,0 +1,62 @@
+use bevy::prelude::*;
+
+use crate::{match_::Player, FORWARD_PADDLE_GAP, LEFT_WALL, RIGHT_WALL};
+
+/// Who steers a paddle of a doubles match. Player A and B keep the back paddles of their
+/// sides, C plays forward on A's side and D on B's.
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Seat(pub Player);
+
+impl Seat {
+    /// Side of the arena the seat plays on
+    pub fn side(&self) -> Player {
+        match self.0 {
+            Player::A | Player::C => Player::A,
+            Player::B | Player::D => Player::B,
+        }
+    }
+
+    /// Whether the seat has the paddle nearer the middle, which only stops balls headed for
+    /// its goal
+    pub fn is_forward(&self) -> bool {
+        matches!(self.0, Player::C | Player::D)
+    }
+
+    pub fn start_position(&self) -> Vec3 {
+        match self.0 {
+            Player::C => Vec3::new(LEFT_WALL + FORWARD_PADDLE_GAP, 0., 0.),
+            Player::D => Vec3::new(RIGHT_WALL - FORWARD_PADDLE_GAP, 0., 0.),
+            back => back.start_position(),
+        }
+    }
+}
+
+/// Player whose keys and gamepad steer a paddle of `player`'s side, the seat if it has one
+pub fn seat_of(player: Player, seat: Option<&Seat>) -> Player {
+    seat.map_or(player, |seat| seat.0)
+}
+
+/// Balls a paddle of a doubles match returned so far
+#[derive(Component, Default, Debug)]
+pub struct PaddleHits(pub usize);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forward_seats_play_in_front_of_their_partner() {
+        for (back, forward) in [
+            (Seat(Player::A), Seat(Player::C)),
+            (Seat(Player::B), Seat(Player::D)),
+        ] {
+            assert_eq!(back.side(), forward.side());
+            assert!(!back.is_forward() && forward.is_forward());
+
+            let (back_x, forward_x) = (back.start_position().x, forward.start_position().x);
+            assert_eq!(back_x, back.0.start_position().x);
+            assert!(forward_x.abs() < back_x.abs());
+            assert_eq!(forward_x.signum(), back_x.signum());
+        }
+    }
+}
@@ -0,0 +1,214 @@
+use bevy::prelude::*;
+
+use crate::{
+    despawn_screen, is_match_authority,
+    match_::{
+        match_running, process_score, Goal, OnMatchView, Paddle, Player, RoundState, WallBundle,
+        WallLocation,
+    },
+    scored::InstantReplay,
+    spawn_timed_message, GameState, GameTimer, MatchInfo, RoundData,
+};
+
+pub fn four_player_plugin(app: &mut App) {
+    app.add_systems(
+        FixedUpdate,
+        lose_life
+            .after(process_score)
+            .run_if(resource_exists::<Lives>)
+            .run_if(in_state(RoundState::In).and_then(match_running)),
+    )
+    .add_systems(
+        OnEnter(RoundState::Scored),
+        setup_life_lost.run_if(resource_exists::<Lives>),
+    )
+    .add_systems(
+        OnExit(RoundState::Scored),
+        despawn_screen::<OnLifeLostScreen>,
+    )
+    .add_systems(
+        Update,
+        run_life_lost
+            .run_if(resource_exists::<Lives>.and_then(is_match_authority))
+            .run_if(in_state(RoundState::Scored).and_then(match_running)),
+    );
+}
+
+/// The ball went into the goal of this player, in a four-player match
+#[derive(Event, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct LifeLost(pub Player);
+
+/// Lives left to each player of a four-player match, which has this instead of scores. A
+/// player without lives is out and their goal is walled up.
+#[derive(Resource, Debug)]
+pub struct Lives {
+    left: [usize; 4],
+    /// Player who lost the last life
+    pub last_lost: Option<Player>,
+}
+
+impl Lives {
+    pub fn new(lives: usize) -> Lives {
+        Lives {
+            left: [lives; 4],
+            last_lost: None,
+        }
+    }
+
+    pub fn left(&self, player: Player) -> usize {
+        self.left[player.index()]
+    }
+
+    pub fn lose(&mut self, player: Player) {
+        self.left[player.index()] = self.left(player).saturating_sub(1);
+        self.last_lost = Some(player);
+    }
+
+    /// Players still in the match
+    pub fn survivors(&self) -> impl Iterator<Item = Player> + '_ {
+        Player::ALL
+            .into_iter()
+            .filter(|&player| self.left(player) > 0)
+    }
+
+    /// Winner of the match, once everyone else is out
+    pub fn survivor(&self) -> Option<Player> {
+        let mut survivors = self.survivors();
+        let survivor = survivors.next()?;
+        survivors.next().is_none().then_some(survivor)
+    }
+
+    /// Player serving after `loser` lost a life: `loser` again if still in, or else the next
+    /// player still in after them
+    pub fn next_server(&self, loser: Player) -> Player {
+        let start = loser.index();
+        (0..Player::ALL.len())
+            .map(|offset| Player::ALL[(start + offset) % Player::ALL.len()])
+            .find(|&player| self.left(player) > 0)
+            .unwrap_or(loser)
+    }
+}
+
+#[derive(Component, Clone)]
+struct OnLifeLostScreen;
+
+// The point goes on while other balls are in play, unless a player is out and their goal
+// has to be walled up
+fn lose_life(
+    mut lives: ResMut<Lives>,
+    mut life_events: EventReader<LifeLost>,
+    round_data: Res<RoundData>,
+    mut next_state: ResMut<NextState<RoundState>>,
+) {
+    let mut lost = false;
+    let mut someone_out = false;
+    for &LifeLost(player) in life_events.read() {
+        lives.lose(player);
+        lost = true;
+        someone_out |= lives.left(player) == 0;
+    }
+    if lost && (round_data.balls_in_play == 0 || someone_out) {
+        next_state.set(RoundState::Scored);
+    }
+}
+
+fn setup_life_lost(commands: Commands, lives: Res<Lives>) {
+    let Some(player) = lives.last_lost else {
+        return;
+    };
+    let message = if lives.left(player) == 0 {
+        format!("Player {:?} is out!", player)
+    } else {
+        format!("Player {:?} loses a life!", player)
+    };
+    spawn_timed_message(commands, &message, 2.0, OnLifeLostScreen);
+}
+
+// Once the message and the replay are over, walls up the goals of the players who are out and
+// either serves again or ends the match once at most one player is left
+fn run_life_lost(
+    mut commands: Commands,
+    lives: Res<Lives>,
+    time: Res<Time>,
+    mut timer: ResMut<GameTimer>,
+    mut match_: ResMut<MatchInfo>,
+    instant_replay: Option<Res<InstantReplay>>,
+    paddle_query: Query<(Entity, &Player), With<Paddle>>,
+    goal_query: Query<(Entity, &Goal)>,
+    mut next_state_round: ResMut<NextState<RoundState>>,
+    mut next_state_game: ResMut<NextState<GameState>>,
+) {
+    if instant_replay.is_some() || !timer.tick(time.delta()).finished() {
+        return;
+    }
+    let Some(loser) = lives.last_lost else {
+        return;
+    };
+    match_.round_count += 1;
+
+    // with extra balls, more than one player can be out at once
+    for (entity, &player) in &paddle_query {
+        if lives.left(player) == 0 {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+    for (entity, goal) in &goal_query {
+        if lives.left(goal.0) == 0 {
+            commands.entity(entity).despawn_recursive();
+            commands.spawn((WallBundle::new(WallLocation::behind(goal.0)), OnMatchView));
+        }
+    }
+
+    // the last players can go out together on the same tick, leaving nobody to win
+    if lives.survivors().count() <= 1 {
+        next_state_round.set(RoundState::Out);
+        next_state_game.set(GameState::End);
+        return;
+    }
+    match_.server = lives.next_server(loser);
+    next_state_round.set(RoundState::Countdown);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_last_player_with_lives_wins() {
+        let mut lives = Lives::new(2);
+
+        lives.lose(Player::C);
+        lives.lose(Player::C);
+        assert_eq!(lives.left(Player::C), 0);
+        assert_eq!(lives.survivor(), None);
+        // whoever is out doesn't serve
+        assert_eq!(lives.next_server(Player::C), Player::D);
+
+        lives.lose(Player::A);
+        assert_eq!(lives.next_server(Player::A), Player::A);
+        lives.lose(Player::A);
+        lives.lose(Player::D);
+        lives.lose(Player::D);
+        assert_eq!(lives.next_server(Player::D), Player::B);
+
+        assert_eq!(lives.survivor(), Some(Player::B));
+        assert_eq!(lives.survivors().count(), 1);
+    }
+
+    #[test]
+    fn the_last_players_can_go_out_together() {
+        let mut lives = Lives::new(1);
+
+        lives.lose(Player::A);
+        lives.lose(Player::B);
+        assert_eq!(lives.survivors().count(), 2);
+        assert_eq!(lives.survivor(), None);
+
+        // two balls go in on the same tick
+        lives.lose(Player::C);
+        lives.lose(Player::D);
+        assert_eq!(lives.survivors().count(), 0);
+        assert_eq!(lives.survivor(), None);
+        assert_eq!(lives.next_server(Player::D), Player::D);
+    }
+}
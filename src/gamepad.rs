@@ -0,0 +1,175 @@
+use bevy::{
+    input::gamepad::{GamepadConnection, GamepadConnectionEvent},
+    prelude::*,
+};
+
+use crate::{
+    doubles::{seat_of, Seat},
+    match_::{read_keyboard_input, KeyboardControl, MatchSet, PaddleInput, PauseState, Player},
+    GameState,
+};
+
+pub fn gamepad_plugin(app: &mut App) {
+    app.init_resource::<GamepadAssignment>().add_systems(
+        Update,
+        (
+            assign_gamepads,
+            read_gamepad_input
+                .after(read_keyboard_input)
+                .run_if(in_state(PauseState::Running))
+                .in_set(MatchSet),
+        ),
+    );
+}
+
+/// Gamepads steering the paddles, assigned as they connect or join on the join screen
+#[derive(Resource, Default, Debug)]
+pub struct GamepadAssignment {
+    /// Gamepad of each player, by `Player::index`
+    gamepads: [Option<Gamepad>; 4],
+}
+
+impl GamepadAssignment {
+    pub fn gamepad(&self, player: Player) -> Option<Gamepad> {
+        self.gamepads[player.index()]
+    }
+
+    pub fn player(&self, gamepad: Gamepad) -> Option<Player> {
+        Player::ALL
+            .into_iter()
+            .find(|&player| self.gamepad(player) == Some(gamepad))
+    }
+
+    /// Assigns `gamepad` to the first player without one, None if every player has one
+    pub fn join(&mut self, gamepad: Gamepad) -> Option<Player> {
+        if let Some(player) = self.player(gamepad) {
+            return Some(player);
+        }
+        let player = Player::ALL
+            .into_iter()
+            .find(|&player| self.gamepad(player).is_none())?;
+        self.gamepads[player.index()] = Some(gamepad);
+        Some(player)
+    }
+
+    /// Frees the player `gamepad` was assigned to, returned if there was one
+    pub fn leave(&mut self, gamepad: Gamepad) -> Option<Player> {
+        let player = self.player(gamepad)?;
+        self.gamepads[player.index()] = None;
+        Some(player)
+    }
+}
+
+/// Direction a paddle is steered in from a gamepad, the d-pad at full speed or else the stick
+/// as far as it is pushed
+pub fn gamepad_direction(stick: f32, dpad_up: bool, dpad_down: bool) -> f32 {
+    match (dpad_up, dpad_down) {
+        (true, false) => 1.,
+        (false, true) => -1.,
+        _ => stick.clamp(-1., 1.),
+    }
+}
+
+fn assign_gamepads(
+    mut connection_events: EventReader<GamepadConnectionEvent>,
+    mut assignment: ResMut<GamepadAssignment>,
+    q_local: Query<(&Player, Option<&Seat>), With<KeyboardControl>>,
+    game_state: Res<State<GameState>>,
+    pause_state: Res<State<PauseState>>,
+    mut next_pause_state: ResMut<NextState<PauseState>>,
+) {
+    for event in connection_events.read() {
+        match event.connection {
+            GamepadConnection::Connected(_) => {
+                if let Some(player) = assignment.join(event.gamepad) {
+                    info!("gamepad {} steers player {:?}", event.gamepad.id, player);
+                }
+            }
+            GamepadConnection::Disconnected => {
+                let Some(player) = assignment.leave(event.gamepad) else {
+                    continue;
+                };
+                info!(
+                    "gamepad {} of player {:?} disconnected",
+                    event.gamepad.id, player
+                );
+                // pause rather than leave the paddle without anyone steering it
+                if *game_state.get() == GameState::Match
+                    && *pause_state.get() == PauseState::Running
+                    && q_local
+                        .iter()
+                        .any(|(&local, seat)| seat_of(local, seat) == player)
+                {
+                    next_pause_state.set(PauseState::Paused);
+                }
+            }
+        }
+    }
+}
+
+// Steers paddles with their player's gamepad while it is pushed, and with the keys otherwise
+pub fn read_gamepad_input(
+    assignment: Res<GamepadAssignment>,
+    axes: Res<Axis<GamepadAxis>>,
+    buttons: Res<ButtonInput<GamepadButton>>,
+    mut query: Query<(&mut PaddleInput, &Player, Option<&Seat>), With<KeyboardControl>>,
+) {
+    for (mut input, player, seat) in &mut query {
+        let Some(gamepad) = assignment.gamepad(seat_of(*player, seat)) else {
+            continue;
+        };
+        // paddles along the top and bottom walls steer with the horizontal directions
+        let (axis, up, down) = if player.moves_sideways() {
+            (
+                GamepadAxisType::LeftStickX,
+                GamepadButtonType::DPadRight,
+                GamepadButtonType::DPadLeft,
+            )
+        } else {
+            (
+                GamepadAxisType::LeftStickY,
+                GamepadButtonType::DPadUp,
+                GamepadButtonType::DPadDown,
+            )
+        };
+        let stick = axes.get(GamepadAxis::new(gamepad, axis)).unwrap_or(0.);
+        let direction = gamepad_direction(
+            stick,
+            buttons.pressed(GamepadButton::new(gamepad, up)),
+            buttons.pressed(GamepadButton::new(gamepad, down)),
+        );
+        if direction != 0. {
+            input.0 = direction;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gamepads_join_the_first_free_player() {
+        let mut assignment = GamepadAssignment::default();
+
+        assert_eq!(assignment.join(Gamepad::new(3)), Some(Player::A));
+        assert_eq!(assignment.join(Gamepad::new(5)), Some(Player::B));
+        assert_eq!(assignment.join(Gamepad::new(3)), Some(Player::A));
+        assert_eq!(assignment.join(Gamepad::new(7)), Some(Player::C));
+        assert_eq!(assignment.join(Gamepad::new(8)), Some(Player::D));
+        assert_eq!(assignment.join(Gamepad::new(9)), None);
+
+        assert_eq!(assignment.leave(Gamepad::new(3)), Some(Player::A));
+        assert_eq!(assignment.leave(Gamepad::new(3)), None);
+        assert_eq!(assignment.join(Gamepad::new(9)), Some(Player::A));
+        assert_eq!(assignment.gamepad(Player::B), Some(Gamepad::new(5)));
+    }
+
+    #[test]
+    fn dpad_goes_full_speed_and_the_stick_as_far_as_it_is_pushed() {
+        assert_eq!(gamepad_direction(0.4, false, false), 0.4);
+        assert_eq!(gamepad_direction(-0.25, false, false), -0.25);
+        assert_eq!(gamepad_direction(0.4, false, true), -1.);
+        assert_eq!(gamepad_direction(0., true, true), 0.);
+    }
+}
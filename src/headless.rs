@@ -0,0 +1,190 @@
+use bevy::{prelude::*, time::TimeUpdateStrategy};
+
+use crate::{
+    ai::AiDifficulty, countdown::countdown_plugin, match_::match_plugin,
+    multi_ball::multi_ball_plugin, scored::scored_plugin, setup, GameState, MatchInfo, MatchMode,
+    MatchSeed, Scores,
+};
+
+/// Longest a simulated match may take before it is given up on, about an hour of play
+const MAX_MATCH_TICKS: u32 = 64 * 60 * 60;
+
+/// Final state of a simulated match
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct MatchResult {
+    pub games_a: usize,
+    pub games_b: usize,
+    /// Points in the last game
+    pub score_a: usize,
+    pub score_b: usize,
+    pub rounds: usize,
+}
+
+/// Number of matches to simulate, from `--headless <matches>`
+pub fn matches_from_args() -> Option<usize> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--headless" {
+            return Some(args.next().and_then(|n| n.parse().ok()).unwrap_or(1));
+        }
+    }
+    None
+}
+
+/// AI difficulty of both paddles in simulated matches, from `--ai <difficulty>`
+pub fn difficulty_from_args() -> AiDifficulty {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--ai" {
+            match args.next().as_deref() {
+                Some("easy") => return AiDifficulty::Easy,
+                Some("normal") => return AiDifficulty::Normal,
+                Some("hard") => return AiDifficulty::Hard,
+                Some("perfect") => return AiDifficulty::Perfect,
+                other => eprintln!("ignoring invalid difficulty {:?}", other),
+            }
+        }
+    }
+    AiDifficulty::Normal
+}
+
+/// Seed of the serves of the first simulated match, from `--seed <seed>`
+pub fn seed_from_args() -> Option<u64> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--seed" {
+            return args.next().and_then(|seed| seed.parse().ok());
+        }
+    }
+    None
+}
+
+/// App with the match logic only: no window, rendering or audio.
+///
+/// Every `update` advances time by exactly one fixed tick, so matches play out as fast as the
+/// machine allows. Paddles are steered by `Ai` in `MatchMode::AiVsAi`, or scripted by pressing
+/// keys on the `ButtonInput<KeyCode>` resource in `MatchMode::Local`.
+pub fn headless_app() -> App {
+    let mut app = App::new();
+    app.add_plugins((MinimalPlugins, AssetPlugin::default()))
+        // setup_match still builds the ball mesh and sprites, they are just never drawn
+        .init_asset::<Mesh>()
+        .init_asset::<ColorMaterial>()
+        .init_resource::<ButtonInput<KeyCode>>()
+        .insert_resource(TimeUpdateStrategy::ManualDuration(
+            Time::<Fixed>::default().timestep(),
+        ))
+        .init_state::<GameState>()
+        .add_systems(Startup, setup)
+        .add_plugins((
+            countdown_plugin,
+            scored_plugin,
+            match_plugin,
+            multi_ball_plugin,
+        ));
+    app
+}
+
+/// Plays a match on an app from `headless_app`, serving from `seed` or a random seed.
+/// None if it did not end within `max_ticks`.
+pub fn play_match(
+    app: &mut App,
+    match_mode: MatchMode,
+    seed: Option<u64>,
+    max_ticks: u32,
+) -> Option<MatchResult> {
+    // let startup run first, it resets the match mode
+    app.update();
+    // leave an unfinished match, entering the same state again would not restart it
+    if *app.world.resource::<State<GameState>>() == GameState::Match {
+        app.world
+            .resource_mut::<NextState<GameState>>()
+            .set(GameState::Menu);
+        app.update();
+    }
+    app.insert_resource(match_mode);
+    app.insert_resource(MatchSeed(seed));
+    app.world
+        .resource_mut::<NextState<GameState>>()
+        .set(GameState::Match);
+    app.update();
+
+    for _ in 0..max_ticks {
+        if *app.world.resource::<State<GameState>>() == GameState::End {
+            let scores = app.world.resource::<Scores>();
+            let match_ = app.world.resource::<MatchInfo>();
+            return Some(MatchResult {
+                games_a: match_.games_a,
+                games_b: match_.games_b,
+                score_a: scores.a,
+                score_b: scores.b,
+                rounds: match_.round_count,
+            });
+        }
+        app.update();
+    }
+    None
+}
+
+/// Simulates `matches` AI matches and prints their results, the serves of each match seeded
+/// one after another from `seed`
+pub fn run_matches(matches: usize, difficulty: AiDifficulty, seed: Option<u64>) {
+    let mut app = headless_app();
+    let mut wins = (0, 0);
+    for i in 1..=matches {
+        match play_match(
+            &mut app,
+            MatchMode::AiVsAi(difficulty, difficulty),
+            seed.map(|seed| seed.wrapping_add(i as u64 - 1)),
+            MAX_MATCH_TICKS,
+        ) {
+            Some(result) => {
+                println!(
+                    "match {}: games {} - {}, last game {} - {}, in {} rounds",
+                    i,
+                    result.games_a,
+                    result.games_b,
+                    result.score_a,
+                    result.score_b,
+                    result.rounds
+                );
+                if result.games_a > result.games_b {
+                    wins.0 += 1;
+                } else {
+                    wins.1 += 1;
+                }
+            }
+            None => println!("match {}: did not finish", i),
+        }
+    }
+    println!("Player A won {}, Player B won {}", wins.0, wins.1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{rules::MatchRules, RoundData};
+
+    #[test]
+    fn ai_matches_repeat_with_the_seed() {
+        let play = || {
+            let mut app = headless_app();
+            // let startup load the default rules first
+            app.update();
+            app.insert_resource(MatchRules {
+                points_to_win: 5,
+                ..default()
+            });
+            let result = play_match(
+                &mut app,
+                MatchMode::AiVsAi(AiDifficulty::Hard, AiDifficulty::Normal),
+                Some(5),
+                MAX_MATCH_TICKS,
+            )
+            .expect("match did not end");
+            (result, app.world.resource::<RoundData>().paddle_hit_count)
+        };
+
+        assert_eq!(play(), play());
+    }
+}
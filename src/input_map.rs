@@ -0,0 +1,231 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::match_::Player;
+
+/// Keys with a fixed meaning that can't be bound to an action
+pub const RESERVED_KEYS: [KeyCode; 1] = [
+    // toggles the FPS counter
+    KeyCode::F12,
+];
+
+/// Something a player does with a key
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+    Up,
+    Down,
+    /// Serve without waiting for the end of the countdown
+    Serve,
+    Pause,
+}
+
+impl Action {
+    pub const ALL: [Action; 4] = [Action::Up, Action::Down, Action::Serve, Action::Pause];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Action::Up => "Up",
+            Action::Down => "Down",
+            Action::Serve => "Serve",
+            Action::Pause => "Pause",
+        }
+    }
+
+    /// Label of the action for `player`, whose paddle moves sideways in four-player matches
+    pub fn label_for(&self, player: Player) -> &'static str {
+        match self {
+            Action::Up if player.moves_sideways() => "Up / Right",
+            Action::Down if player.moves_sideways() => "Down / Left",
+            _ => self.label(),
+        }
+    }
+}
+
+/// Keys bound to the actions of one player
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ActionKeys {
+    pub up: KeyCode,
+    pub down: KeyCode,
+    pub serve: KeyCode,
+    pub pause: KeyCode,
+}
+
+impl ActionKeys {
+    pub fn key(&self, action: Action) -> KeyCode {
+        match action {
+            Action::Up => self.up,
+            Action::Down => self.down,
+            Action::Serve => self.serve,
+            Action::Pause => self.pause,
+        }
+    }
+
+    fn key_mut(&mut self, action: Action) -> &mut KeyCode {
+        match action {
+            Action::Up => &mut self.up,
+            Action::Down => &mut self.down,
+            Action::Serve => &mut self.serve,
+            Action::Pause => &mut self.pause,
+        }
+    }
+}
+
+/// Why a key can't be bound
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Conflict {
+    /// The key is already bound to another action
+    Bound(Player, Action),
+    Reserved,
+}
+
+/// Keys each player's actions are bound to, saved with the settings. Up and down move the
+/// paddles of players C and D right and left.
+#[derive(Resource, Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub struct InputMap {
+    pub a: ActionKeys,
+    pub b: ActionKeys,
+    pub c: ActionKeys,
+    pub d: ActionKeys,
+}
+
+impl Default for InputMap {
+    fn default() -> Self {
+        InputMap {
+            a: ActionKeys {
+                up: KeyCode::KeyW,
+                down: KeyCode::KeyS,
+                serve: KeyCode::Space,
+                pause: KeyCode::Escape,
+            },
+            b: ActionKeys {
+                up: KeyCode::ArrowUp,
+                down: KeyCode::ArrowDown,
+                serve: KeyCode::Enter,
+                pause: KeyCode::Escape,
+            },
+            c: ActionKeys {
+                up: KeyCode::KeyL,
+                down: KeyCode::KeyJ,
+                serve: KeyCode::KeyK,
+                pause: KeyCode::Escape,
+            },
+            d: ActionKeys {
+                up: KeyCode::Numpad6,
+                down: KeyCode::Numpad4,
+                serve: KeyCode::Numpad5,
+                pause: KeyCode::Escape,
+            },
+        }
+    }
+}
+
+impl InputMap {
+    pub fn keys(&self, player: Player) -> &ActionKeys {
+        match player {
+            Player::A => &self.a,
+            Player::B => &self.b,
+            Player::C => &self.c,
+            Player::D => &self.d,
+        }
+    }
+
+    pub fn key(&self, player: Player, action: Action) -> KeyCode {
+        self.keys(player).key(action)
+    }
+
+    /// What binding `key` to `action` of `player` would clash with. All players may pause
+    /// with the same key, pausing does the same whoever presses it.
+    pub fn conflict(&self, player: Player, action: Action, key: KeyCode) -> Option<Conflict> {
+        if RESERVED_KEYS.contains(&key) {
+            return Some(Conflict::Reserved);
+        }
+        Player::ALL
+            .into_iter()
+            .flat_map(|other| Action::ALL.map(|other_action| (other, other_action)))
+            .filter(|&(other, other_action)| {
+                other_action != action || (other != player && action != Action::Pause)
+            })
+            .find(|&(other, other_action)| self.key(other, other_action) == key)
+            .map(|(other, other_action)| Conflict::Bound(other, other_action))
+    }
+
+    /// Binds `key` to `action` of `player`, unless it clashes with another binding
+    pub fn rebind(&mut self, player: Player, action: Action, key: KeyCode) -> Result<(), Conflict> {
+        if let Some(conflict) = self.conflict(player, action, key) {
+            return Err(conflict);
+        }
+        let keys = match player {
+            Player::A => &mut self.a,
+            Player::B => &mut self.b,
+            Player::C => &mut self.c,
+            Player::D => &mut self.d,
+        };
+        *keys.key_mut(action) = key;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rebinding_to_a_bound_key_reports_the_other_binding() {
+        let mut input_map = InputMap::default();
+
+        assert_eq!(
+            input_map.rebind(Player::A, Action::Up, KeyCode::ArrowDown),
+            Err(Conflict::Bound(Player::B, Action::Down))
+        );
+        assert_eq!(
+            input_map.rebind(Player::A, Action::Serve, KeyCode::KeyW),
+            Err(Conflict::Bound(Player::A, Action::Up))
+        );
+        assert_eq!(
+            input_map.rebind(Player::B, Action::Up, KeyCode::F12),
+            Err(Conflict::Reserved)
+        );
+        assert_eq!(input_map, InputMap::default());
+    }
+
+    #[test]
+    fn rebinding_to_a_free_key_replaces_the_old_one() {
+        let mut input_map = InputMap::default();
+
+        assert_eq!(
+            input_map.rebind(Player::A, Action::Up, KeyCode::KeyI),
+            Ok(())
+        );
+        // rebinding to the key already bound to that action changes nothing
+        assert_eq!(
+            input_map.rebind(Player::A, Action::Down, KeyCode::KeyS),
+            Ok(())
+        );
+
+        assert_eq!(input_map.key(Player::A, Action::Up), KeyCode::KeyI);
+        assert_eq!(input_map.key(Player::A, Action::Down), KeyCode::KeyS);
+        assert_eq!(
+            input_map.conflict(Player::B, Action::Up, KeyCode::KeyW),
+            None
+        );
+    }
+
+    #[test]
+    fn players_can_share_the_pause_key_only() {
+        let mut input_map = InputMap::default();
+
+        assert_eq!(
+            input_map.rebind(Player::B, Action::Pause, KeyCode::KeyP),
+            Ok(())
+        );
+        assert_eq!(
+            input_map.rebind(Player::A, Action::Pause, KeyCode::KeyP),
+            Ok(())
+        );
+        assert_eq!(
+            input_map.rebind(Player::B, Action::Serve, KeyCode::Space),
+            Err(Conflict::Bound(Player::A, Action::Serve))
+        );
+    }
+}
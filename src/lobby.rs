@@ -0,0 +1,192 @@
+use std::{
+    io::ErrorKind,
+    net::{Ipv4Addr, SocketAddr, UdpSocket},
+    time::Instant,
+};
+
+use bevy::prelude::*;
+
+use crate::{
+    net::{Message, NetConfig, NetRole, NetSession},
+    rules::MatchRules,
+};
+
+pub const DISCOVERY_PORT: u16 = 7879;
+
+const ANNOUNCE_INTERVAL: f32 = 1.;
+
+/// Seconds a game stays listed after its last announcement
+const GAME_EXPIRY: f32 = 3.5;
+
+pub fn lobby_plugin(app: &mut App) {
+    app.add_systems(
+        Update,
+        (
+            announce_game.run_if(resource_exists::<NetSession>),
+            discover_games.run_if(resource_exists::<LanLobby>),
+        ),
+    );
+}
+
+pub struct LanGame {
+    /// Address of the game socket of the host
+    pub addr: SocketAddr,
+    pub name: String,
+    pub ruleset: String,
+    /// Round trip time in milliseconds, once measured
+    pub ping: Option<f32>,
+    last_seen: f32,
+    ping_sent: Option<(u32, Instant)>,
+}
+
+/// Games announced on the local network, listened for while the network menu is open
+#[derive(Resource, Default)]
+pub struct LanLobby {
+    // None while another copy of the game on this machine holds the discovery port
+    socket: Option<UdpSocket>,
+    pub games: Vec<LanGame>,
+    next_nonce: u32,
+    last_bind_attempt: Option<f32>,
+}
+
+impl LanLobby {
+    fn bind(&mut self) {
+        let socket = UdpSocket::bind(("0.0.0.0", DISCOVERY_PORT))
+            .and_then(|socket| socket.set_nonblocking(true).map(|_| socket));
+        match socket {
+            Ok(socket) => self.socket = Some(socket),
+            Err(err) => debug!("can't listen for games yet: {}", err),
+        }
+    }
+}
+
+fn announce_game(
+    session: Res<NetSession>,
+    config: Res<NetConfig>,
+    rules: Res<MatchRules>,
+    time: Res<Time<Real>>,
+    mut socket: Local<Option<UdpSocket>>,
+    mut last_sent: Local<Option<f32>>,
+) {
+    if session.role != NetRole::Host || session.connected {
+        return;
+    }
+    let now = time.elapsed_seconds();
+    if last_sent.is_some_and(|sent| now - sent < ANNOUNCE_INTERVAL) {
+        return;
+    }
+    *last_sent = Some(now);
+
+    if socket.is_none() {
+        let bound = UdpSocket::bind(("0.0.0.0", 0))
+            .and_then(|socket| socket.set_broadcast(true).map(|_| socket));
+        match bound {
+            Ok(bound) => *socket = Some(bound),
+            Err(err) => {
+                warn!("can't announce game: {}", err);
+                return;
+            }
+        }
+    }
+    let (Some(socket), Some(port)) = (socket.as_ref(), session.port()) else {
+        return;
+    };
+
+    let announcement = Message::Announce {
+        name: config.name.clone(),
+        port,
+        ruleset: rules.describe(),
+    }
+    .encode();
+    // without a network to broadcast on, still let copies on this machine find the game
+    if socket
+        .send_to(&announcement, (Ipv4Addr::BROADCAST, DISCOVERY_PORT))
+        .is_err()
+    {
+        socket
+            .send_to(&announcement, (Ipv4Addr::LOCALHOST, DISCOVERY_PORT))
+            .ok();
+    }
+}
+
+fn discover_games(mut lobby: ResMut<LanLobby>, time: Res<Time<Real>>) {
+    let now = time.elapsed_seconds();
+    let lobby = lobby.as_mut();
+
+    if lobby.socket.is_none() {
+        if lobby
+            .last_bind_attempt
+            .is_some_and(|attempt| now - attempt < ANNOUNCE_INTERVAL)
+        {
+            return;
+        }
+        lobby.last_bind_attempt = Some(now);
+        lobby.bind();
+    }
+    let Some(socket) = &lobby.socket else {
+        return;
+    };
+
+    let mut buf = [0; 1024];
+    loop {
+        let (len, from) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+            Err(err) => {
+                debug!("discovery receive failed: {}", err);
+                break;
+            }
+        };
+
+        match Message::decode(&buf[..len]) {
+            Some(Message::Announce {
+                name,
+                port,
+                ruleset,
+            }) => {
+                let addr = SocketAddr::new(from.ip(), port);
+                let index = match lobby.games.iter().position(|game| game.addr == addr) {
+                    Some(index) => index,
+                    None => {
+                        info!("found game {} at {}", name, addr);
+                        lobby.games.push(LanGame {
+                            addr,
+                            name: String::new(),
+                            ruleset: String::new(),
+                            ping: None,
+                            last_seen: now,
+                            ping_sent: None,
+                        });
+                        lobby.games.len() - 1
+                    }
+                };
+                let game = &mut lobby.games[index];
+                game.name = name;
+                game.ruleset = ruleset;
+                game.last_seen = now;
+
+                // the host answers pings on its game port
+                lobby.next_nonce = lobby.next_nonce.wrapping_add(1);
+                game.ping_sent = Some((lobby.next_nonce, Instant::now()));
+                socket
+                    .send_to(&Message::Ping(lobby.next_nonce).encode(), addr)
+                    .ok();
+            }
+            Some(Message::Pong(nonce)) => {
+                if let Some(game) = lobby
+                    .games
+                    .iter_mut()
+                    .find(|game| game.ping_sent.is_some_and(|(sent, _)| sent == nonce))
+                {
+                    let (_, sent_at) = game.ping_sent.take().unwrap();
+                    game.ping = Some(sent_at.elapsed().as_secs_f32() * 1000.);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    lobby
+        .games
+        .retain(|game| now - game.last_seen < GAME_EXPIRY);
+}
@@ -1,4 +1,6 @@
 #![allow(unused)]
+// bevy systems take many, deeply generic parameters
+#![allow(clippy::too_many_arguments, clippy::type_complexity)]
 
 use bevy::{diagnostic::FrameTimeDiagnosticsPlugin, prelude::*};
 use bevy_vector_shapes::prelude::*;
@@ -8,25 +10,39 @@ use match_::{run_match, update_score_ui};
 use menu::OnMenuScreen;
 use systems::*;
 
+mod ai;
 mod countdown;
+mod doubles;
+mod four_player;
 mod fps;
+mod gamepad;
+mod headless;
+mod input_map;
+mod lobby;
 mod match_;
 mod menu;
+mod multi_ball;
+mod net;
+mod pointer;
+mod profiles;
+mod replay;
+mod rollback;
+mod rules;
 mod scored;
+mod settings;
 mod systems;
+mod tournament;
 
 const PADDLE_SIZE: Vec3 = Vec3::new(20., 150., 0.0);
 const GAP_BETWEEN_PADDLE_AND_GOAL: f32 = 60.0;
+/// Distance from the goal to the forward paddle of a doubles side
+const FORWARD_PADDLE_GAP: f32 = 3. * GAP_BETWEEN_PADDLE_AND_GOAL;
 const PADDLE_SPEED: f32 = 500.;
 
 const BALL_START_POSITION: Vec3 = Vec3::new(0., 0., 1.);
 const BALL_RADIUS: f32 = 10.;
 const BALL_START_SPEED: f32 = 800.;
 
-const X: f32 = -1.0;
-const Y: f32 = 0.;
-const BALL_START_VELOCITY: Vec2 = Vec2::new(X * BALL_START_SPEED, Y * BALL_START_SPEED);
-
 const WALL_THICKNESS: f32 = 10.;
 const LEFT_WALL: f32 = -450.;
 const RIGHT_WALL: f32 = 450.;
@@ -64,9 +80,16 @@ const TEXT_COLOR: Color = Color::rgb(0., 1., 0.);
 const PADDLE_A_START_POSITION: Vec3 = Vec3::new(LEFT_WALL + GAP_BETWEEN_PADDLE_AND_GOAL, 0., 0.);
 const PADDLE_B_START_POSITION: Vec3 = Vec3::new(RIGHT_WALL - GAP_BETWEEN_PADDLE_AND_GOAL, 0., 0.);
 
-const ROUNDS_TOTAL: usize = 2;
-
 fn main() {
+    if let Some(matches) = headless::matches_from_args() {
+        headless::run_matches(
+            matches,
+            headless::difficulty_from_args(),
+            headless::seed_from_args(),
+        );
+        return;
+    }
+
     App::new()
         .add_plugins(DefaultPlugins.set(WindowPlugin {
             primary_window: Some(Window {
@@ -75,17 +98,41 @@ fn main() {
             }),
             ..default()
         }))
-        .add_plugins(FrameTimeDiagnosticsPlugin::default())
+        .add_plugins(FrameTimeDiagnosticsPlugin)
         .add_systems(Update, (fps_text_update_system, fps_counter_showhide))
-        .add_plugins(Shape2dPlugin::default())
+        .add_plugins(Shape2dPlugin)
         .insert_resource(ClearColor(BACKGROUND_COLOR))
+        .init_resource::<settings::SettingsFile>()
+        .init_resource::<replay::ReplayDir>()
+        .init_resource::<profiles::ProfilesFile>()
+        .init_resource::<tournament::TournamentFile>()
         .init_state::<GameState>()
-        .add_systems(Startup, (setup, setup_fps_counter))
+        .add_systems(
+            Startup,
+            (
+                setup,
+                setup_camera,
+                load_collision_sounds,
+                setup_fps_counter,
+            ),
+        )
+        .add_systems(Update, run_match.run_if(in_state(GameState::Match)))
         .add_plugins((
             menu::menu_plugin,
             countdown::countdown_plugin,
             scored::scored_plugin,
             match_::match_plugin,
+            net::net_plugin,
+            rollback::rollback_plugin,
+            lobby::lobby_plugin,
+            settings::settings_plugin,
+            gamepad::gamepad_plugin,
+            pointer::pointer_plugin,
+            replay::replay_plugin,
+            profiles::profiles_plugin,
+            tournament::tournament_plugin,
+            four_player::four_player_plugin,
+            multi_ball::multi_ball_plugin,
         ))
         .run();
 }
@@ -95,6 +142,8 @@ enum GameState {
     #[default]
     Menu,
     Match,
+    /// Passed through for a frame to start the match over, entering Match again would do nothing
+    Restarting,
     End,
 }
 
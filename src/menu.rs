@@ -1,21 +1,156 @@
-use bevy::{app::AppExit, prelude::*};
+use std::{net::SocketAddr, path::PathBuf, time::SystemTime};
 
-use crate::{despawn_screen, GameState, TEXT_COLOR};
+use bevy::{app::AppExit, prelude::*, ui::RelativeCursorPosition, window::WindowMode};
+use bevy_vector_shapes::{painter::ShapePainter, shapes::LinePainter};
+
+use crate::{
+    ai::AiDifficulty,
+    despawn_screen,
+    doubles::{seat_of, Seat},
+    gamepad::GamepadAssignment,
+    input_map::{Action, Conflict, InputMap},
+    lobby::{LanGame, LanLobby},
+    match_::{KeyboardControl, PauseState, Player},
+    net::{NetConfig, NetSession},
+    profiles::{
+        save_profiles, NameError, Profile, Profiles, ProfilesFile, MAX_NAME_LEN, START_RATING,
+    },
+    replay::{load_replay, ReplayDir, ReplayPlayer},
+    rules::{ExtraBalls, MatchRules, ServeRule},
+    settings::{save_settings, Settings},
+    tournament::{
+        Bracket, BracketFormat, Entrant, Tournament, TournamentFile, TournamentMatch, MAX_PLAYERS,
+        MIN_PLAYERS,
+    },
+    GameState, MatchMode, PADDLE_SPEED, TEXT_COLOR,
+};
 
 pub fn menu_plugin(app: &mut App) {
     app.init_state::<MenuState>()
+        .init_resource::<Rebinding>()
+        .init_resource::<ProfileNameEntry>()
+        .init_resource::<TournamentEntry>()
+        .init_resource::<BracketView>()
         .add_systems(OnEnter(GameState::Menu), enter_menu_state)
+        .add_systems(OnExit(GameState::Menu), exit_menu_state)
         .add_systems(OnEnter(MenuState::Main), setup_menu)
         .add_systems(OnExit(MenuState::Main), despawn_screen::<OnMenuScreen>)
+        .add_systems(OnEnter(MenuState::VsAi), setup_menu_vs_ai)
+        .add_systems(OnExit(MenuState::VsAi), despawn_screen::<OnVsAiMenuScreen>)
         .add_systems(OnEnter(MenuState::Multiplayer), setup_menu_multiplayer)
         .add_systems(
             OnExit(MenuState::Multiplayer),
             despawn_screen::<OnMultiplayerMenuScreen>,
         )
+        .add_systems(OnEnter(MenuState::Replays), setup_menu_replays)
+        .add_systems(
+            OnExit(MenuState::Replays),
+            despawn_screen::<OnReplaysMenuScreen>,
+        )
+        .add_systems(OnEnter(MenuState::Stats), setup_menu_stats)
+        .add_systems(
+            OnExit(MenuState::Stats),
+            (
+                despawn_screen::<OnStatsMenuScreen>,
+                stop_profile_name_entry,
+                save_profiles,
+            ),
+        )
+        .add_systems(OnEnter(MenuState::Leaderboard), setup_menu_leaderboard)
+        .add_systems(
+            OnExit(MenuState::Leaderboard),
+            despawn_screen::<OnLeaderboardMenuScreen>,
+        )
+        .add_systems(
+            OnEnter(MenuState::TournamentEntry),
+            setup_menu_tournament_entry,
+        )
+        .add_systems(
+            OnExit(MenuState::TournamentEntry),
+            despawn_screen::<OnTournamentEntryMenuScreen>,
+        )
+        .add_systems(OnEnter(MenuState::Tournament), setup_menu_tournament)
+        .add_systems(
+            OnExit(MenuState::Tournament),
+            despawn_screen::<OnTournamentMenuScreen>,
+        )
+        .add_systems(OnEnter(MenuState::Join), setup_menu_join)
+        .add_systems(
+            OnExit(MenuState::Join),
+            (despawn_screen::<OnJoinMenuScreen>, save_profiles),
+        )
+        .add_systems(OnEnter(MenuState::MatchRules), setup_menu_match_rules)
+        .add_systems(
+            OnExit(MenuState::MatchRules),
+            (despawn_screen::<OnMatchRulesMenuScreen>, save_settings),
+        )
+        .add_systems(OnEnter(MenuState::Settings), setup_menu_settings)
+        .add_systems(
+            OnExit(MenuState::Settings),
+            (
+                despawn_screen::<OnSettingsMenuScreen>,
+                stop_rebinding,
+                save_settings,
+            ),
+        )
+        .add_systems(
+            OnEnter(MenuState::SettingsMatchRules),
+            setup_menu_match_rules,
+        )
+        .add_systems(
+            OnExit(MenuState::SettingsMatchRules),
+            (despawn_screen::<OnMatchRulesMenuScreen>, save_settings),
+        )
+        .add_systems(
+            OnEnter(MenuState::MultiplayerNetwork),
+            (setup_menu_multiplayer_network, open_lobby),
+        )
+        .add_systems(
+            OnExit(MenuState::MultiplayerNetwork),
+            (
+                despawn_screen::<OnMultiplayerNetworkMenuScreen>,
+                close_lobby,
+            ),
+        )
+        .add_systems(OnEnter(PauseState::Paused), setup_pause_menu)
+        .add_systems(
+            OnExit(PauseState::Paused),
+            despawn_screen::<OnPauseMenuScreen>,
+        )
+        .add_systems(OnEnter(PauseState::Settings), open_pause_settings)
+        .add_systems(OnExit(PauseState::Settings), close_pause_settings)
         .add_systems(
             Update,
-            (menu_action, button_system, bevy::window::close_on_esc)
-                .run_if(in_state(GameState::Menu)),
+            (
+                (menu_action, button_system)
+                    .run_if(in_state(GameState::Menu).or_else(not(in_state(PauseState::Running)))),
+                (
+                    option_button::<RuleOption>,
+                    option_button::<SettingOption>,
+                    option_button::<TournamentFormatOption>,
+                    option_button::<BracketViewOption>,
+                    volume_slider,
+                    rebind_keys,
+                )
+                    .run_if(not(in_state(MenuState::Disabled))),
+                (
+                    bevy::window::close_on_esc
+                        .after(rebind_keys)
+                        .after(enter_profile_name)
+                        .after(enter_tournament_names),
+                    (join_gamepads, update_profile_slots).run_if(in_state(MenuState::Join)),
+                    (enter_profile_name, update_profile_list).run_if(in_state(MenuState::Stats)),
+                    draw_rating_graph.run_if(in_state(MenuState::Leaderboard)),
+                    enter_tournament_names.run_if(in_state(MenuState::TournamentEntry)),
+                    update_bracket.run_if(in_state(MenuState::Tournament)),
+                    (update_network_status, update_lan_games)
+                        .run_if(in_state(MenuState::MultiplayerNetwork)),
+                )
+                    .run_if(in_state(GameState::Menu)),
+                toggle_pause
+                    .after(rebind_keys)
+                    .run_if(in_state(GameState::Match)),
+            ),
         );
 }
 
@@ -26,6 +161,19 @@ enum MenuState {
     Multiplayer,
     MultiplayerLocal,
     MultiplayerNetwork,
+    // players of a new tournament are entered
+    TournamentEntry,
+    // the bracket of the tournament, between its matches
+    Tournament,
+    Replays,
+    Stats,
+    Leaderboard,
+    // gamepads join the players of a local match
+    Join,
+    MatchRules,
+    Settings,
+    // the match rules screen opened from the settings
+    SettingsMatchRules,
     #[default]
     Disabled,
 }
@@ -39,11 +187,114 @@ struct OnMultiplayerMenuScreen;
 #[derive(Component)]
 struct OnVsAiMenuScreen;
 
+#[derive(Component)]
+struct OnMultiplayerNetworkMenuScreen;
+
+#[derive(Component)]
+struct OnJoinMenuScreen;
+
+#[derive(Component)]
+struct OnReplaysMenuScreen;
+
+#[derive(Component)]
+struct OnTournamentEntryMenuScreen;
+
+#[derive(Component)]
+struct OnTournamentMenuScreen;
+
+#[derive(Component)]
+struct TournamentNamesText;
+
+// Holds a column for each round of the bracket shown
+#[derive(Component)]
+struct BracketColumns;
+
+/// Players and format of the tournament being set up
+#[derive(Resource, Default)]
+struct TournamentEntry {
+    names: Vec<String>,
+    // name being typed
+    typed: String,
+    format: BracketFormat,
+    // why the name or the tournament was refused
+    error: Option<String>,
+}
+
+/// Bracket of a double elimination shown, the grand final is shown with the winners bracket
+#[derive(Resource, Default)]
+struct BracketView(Bracket);
+
+#[derive(Component)]
+struct OnStatsMenuScreen;
+
+#[derive(Component)]
+struct OnLeaderboardMenuScreen;
+
+#[derive(Component)]
+struct ExportStatusText;
+
+// Holds a row of stats for each profile
+#[derive(Component)]
+struct ProfileList;
+
+#[derive(Component)]
+struct ProfileNameText;
+
+// Shows the profile each player of the match plays as
+#[derive(Component)]
+struct ProfileSlot(Player);
+
+/// Name being typed for a new profile
+#[derive(Resource, Default)]
+struct ProfileNameEntry {
+    // None unless a profile is being created
+    name: Option<String>,
+    // why the name was refused
+    error: Option<&'static str>,
+}
+
+#[derive(Component)]
+struct OnMatchRulesMenuScreen;
+
+#[derive(Component)]
+struct OnPauseMenuScreen;
+
+#[derive(Component)]
+struct OnSettingsMenuScreen;
+
+#[derive(Component)]
+struct NetworkStatusText;
+
+// Holds a button for each game found on the local network
+#[derive(Component)]
+struct LanGameList;
+
+#[derive(Component)]
+struct LanGameText;
+
 const NORMAL_BUTTON: Color = Color::rgb(0.15, 0.15, 0.15);
 const HOVERED_BUTTON: Color = Color::rgb(0.25, 0.25, 0.25);
 const HOVERED_PRESSED_BUTTON: Color = Color::rgb(0.25, 0.65, 0.25);
 const PRESSED_BUTTON: Color = Color::rgb(0.35, 0.75, 0.35);
 
+/// Recorded matches listed on the replays screen
+const LISTED_REPLAYS: usize = 8;
+
+/// Best rated profiles listed on the leaderboard, and drawn on its graph
+const LISTED_PROFILES: usize = 8;
+
+/// Colors of the profiles on the leaderboard, by rank
+const PROFILE_COLORS: [Color; LISTED_PROFILES] = [
+    Color::GOLD,
+    Color::CYAN,
+    Color::ORANGE_RED,
+    Color::LIME_GREEN,
+    Color::VIOLET,
+    Color::ORANGE,
+    Color::PINK,
+    Color::SILVER,
+];
+
 // Tag component used to mark which setting is currently selected
 #[derive(Component)]
 struct SelectedOption;
@@ -52,13 +303,255 @@ struct SelectedOption;
 #[derive(Component)]
 enum MenuButtonAction {
     PlayVsAi,
+    StartVsAi(AiDifficulty),
     Multiplayer,
     MultiplayerLocal,
+    MultiplayerFourPlayer,
+    MultiplayerDoubles,
     MultiplayerNetwork,
+    Tournament,
+    AddProfilePlayers,
+    StartTournament,
+    PlayTournamentMatch,
+    EndTournament,
+    HostNetwork,
+    JoinNetwork,
+    JoinLanGame(SocketAddr),
+    Replays,
+    PlayReplay(PathBuf),
+    Stats,
+    NewProfile,
+    Leaderboard,
+    ExportHistory,
+    PickProfile(Player),
+    SwitchSeatAi(Player),
+    PickMatchRules,
+    StartMatch,
+    Settings,
+    SettingsMatchRules,
+    BackFromSettings,
+    Resume,
+    RestartMatch,
+    QuitToMenu,
     BackToMenu,
     Quit,
 }
 
+// One choice for one of the match rules, the buttons of a rule form a row
+#[derive(Component, Clone, Copy, PartialEq)]
+enum RuleOption {
+    PointsToWin(usize),
+    BestOf(usize),
+    WinBy(usize),
+    DeuceCap(Option<usize>),
+    Serve(ServeRule),
+    ServeCone(usize),
+    MaxBounceAngle(usize),
+    Spin(bool),
+    Lives(usize),
+    ExtraBalls(ExtraBalls),
+}
+
+impl MenuOption for RuleOption {
+    type Target = MatchRules;
+
+    fn label(&self) -> String {
+        match self {
+            RuleOption::PointsToWin(points) => points.to_string(),
+            RuleOption::BestOf(games) => games.to_string(),
+            RuleOption::WinBy(points) => points.to_string(),
+            RuleOption::DeuceCap(None) => "Off".to_string(),
+            RuleOption::DeuceCap(Some(cap)) => format!("+{}", cap),
+            RuleOption::Serve(ServeRule::Winner) => "Scorer".to_string(),
+            RuleOption::Serve(ServeRule::Loser) => "Scored on".to_string(),
+            RuleOption::Serve(ServeRule::Alternate) => "Alternate".to_string(),
+            RuleOption::ServeCone(0) => "Straight".to_string(),
+            RuleOption::ServeCone(degrees) => format!("{} deg", degrees),
+            RuleOption::MaxBounceAngle(degrees) => format!("{} deg", degrees),
+            RuleOption::Spin(true) => "On".to_string(),
+            RuleOption::Spin(false) => "Off".to_string(),
+            RuleOption::Lives(lives) => lives.to_string(),
+            RuleOption::ExtraBalls(ExtraBalls::Off) => "Off".to_string(),
+            RuleOption::ExtraBalls(ExtraBalls::Timed) => "Timed".to_string(),
+            RuleOption::ExtraBalls(ExtraBalls::PowerUp) => "Power-up".to_string(),
+        }
+    }
+
+    fn is_selected(&self, rules: &MatchRules) -> bool {
+        match *self {
+            RuleOption::PointsToWin(points) => rules.points_to_win == points,
+            RuleOption::BestOf(games) => rules.best_of == games,
+            RuleOption::WinBy(points) => rules.win_by == points,
+            RuleOption::DeuceCap(cap) => rules.deuce_cap == cap,
+            RuleOption::Serve(serve) => rules.serve == serve,
+            RuleOption::ServeCone(degrees) => rules.serve_cone == degrees,
+            RuleOption::MaxBounceAngle(degrees) => rules.max_bounce_angle == degrees,
+            RuleOption::Spin(spin) => rules.spin == spin,
+            RuleOption::Lives(lives) => rules.lives == lives,
+            RuleOption::ExtraBalls(extra_balls) => rules.extra_balls == extra_balls,
+        }
+    }
+
+    fn apply(&self, rules: &mut MatchRules) {
+        match *self {
+            RuleOption::PointsToWin(points) => rules.points_to_win = points,
+            RuleOption::BestOf(games) => rules.best_of = games,
+            RuleOption::WinBy(points) => rules.win_by = points,
+            RuleOption::DeuceCap(cap) => rules.deuce_cap = cap,
+            RuleOption::Serve(serve) => rules.serve = serve,
+            RuleOption::ServeCone(degrees) => rules.serve_cone = degrees,
+            RuleOption::MaxBounceAngle(degrees) => rules.max_bounce_angle = degrees,
+            RuleOption::Spin(spin) => rules.spin = spin,
+            RuleOption::Lives(lives) => rules.lives = lives,
+            RuleOption::ExtraBalls(extra_balls) => rules.extra_balls = extra_balls,
+        }
+    }
+}
+
+// One choice for one of the settings picked from a row of buttons
+#[derive(Component, Clone, Copy, PartialEq)]
+enum SettingOption {
+    ShowFps(bool),
+    WindowMode(WindowMode),
+    PointerControl(Option<Player>),
+    PointerMaxSpeed(f32),
+}
+
+impl MenuOption for SettingOption {
+    type Target = Settings;
+
+    fn label(&self) -> String {
+        match self {
+            SettingOption::ShowFps(true) => "Shown".to_string(),
+            SettingOption::ShowFps(false) => "Hidden".to_string(),
+            SettingOption::WindowMode(WindowMode::Windowed) => "Window".to_string(),
+            SettingOption::WindowMode(WindowMode::BorderlessFullscreen) => "Borderless".to_string(),
+            SettingOption::WindowMode(_) => "Fullscreen".to_string(),
+            SettingOption::PointerControl(None) => "Off".to_string(),
+            SettingOption::PointerControl(Some(player)) => format!("Player {:?}", player),
+            SettingOption::PointerMaxSpeed(speed) => {
+                format!("{}%", (speed / PADDLE_SPEED * 100.).round())
+            }
+        }
+    }
+
+    fn is_selected(&self, settings: &Settings) -> bool {
+        match *self {
+            SettingOption::ShowFps(show) => settings.show_fps == show,
+            SettingOption::WindowMode(mode) => settings.window_mode == mode,
+            SettingOption::PointerControl(player) => settings.pointer_control == player,
+            SettingOption::PointerMaxSpeed(speed) => settings.pointer_max_speed == speed,
+        }
+    }
+
+    fn apply(&self, settings: &mut Settings) {
+        match *self {
+            SettingOption::ShowFps(show) => settings.show_fps = show,
+            SettingOption::WindowMode(mode) => settings.window_mode = mode,
+            SettingOption::PointerControl(player) => settings.pointer_control = player,
+            SettingOption::PointerMaxSpeed(speed) => settings.pointer_max_speed = speed,
+        }
+    }
+}
+
+#[derive(Component, Clone, Copy, PartialEq)]
+struct TournamentFormatOption(BracketFormat);
+
+impl MenuOption for TournamentFormatOption {
+    type Target = TournamentEntry;
+
+    fn label(&self) -> String {
+        match self.0 {
+            BracketFormat::SingleElimination => "Single".to_string(),
+            BracketFormat::DoubleElimination => "Double".to_string(),
+        }
+    }
+
+    fn is_selected(&self, entry: &TournamentEntry) -> bool {
+        entry.format == self.0
+    }
+
+    fn apply(&self, entry: &mut TournamentEntry) {
+        entry.format = self.0;
+    }
+}
+
+#[derive(Component, Clone, Copy, PartialEq)]
+struct BracketViewOption(Bracket);
+
+impl MenuOption for BracketViewOption {
+    type Target = BracketView;
+
+    fn label(&self) -> String {
+        match self.0 {
+            Bracket::Losers => "Losers".to_string(),
+            _ => "Winners".to_string(),
+        }
+    }
+
+    fn is_selected(&self, view: &BracketView) -> bool {
+        view.0 == self.0
+    }
+
+    fn apply(&self, view: &mut BracketView) {
+        view.0 = self.0;
+    }
+}
+
+// Buttons for the values of one option, the buttons of an option form a row
+trait MenuOption: Component + Copy {
+    type Target: Resource;
+
+    fn label(&self) -> String;
+
+    fn is_selected(&self, target: &Self::Target) -> bool;
+
+    fn apply(&self, target: &mut Self::Target);
+
+    fn same_row(&self, other: &Self) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+}
+
+// Which volume a slider sets
+#[derive(Component, Clone, Copy)]
+enum VolumeSlider {
+    Master,
+    Effects,
+}
+
+impl VolumeSlider {
+    fn value(&self, settings: &Settings) -> f32 {
+        match self {
+            VolumeSlider::Master => settings.master_volume,
+            VolumeSlider::Effects => settings.effects_volume,
+        }
+    }
+}
+
+// The filled part of a volume slider, as wide as the volume
+#[derive(Component)]
+struct SliderFill;
+
+// Binds the next key pressed to one action of one player
+#[derive(Component, Clone, Copy, PartialEq, Eq)]
+struct RebindButton {
+    player: Player,
+    action: Action,
+}
+
+// The key shown on a rebind button
+#[derive(Component)]
+struct RebindKey;
+
+// Tells why the last key pressed could not be bound
+#[derive(Component)]
+struct RebindStatus;
+
+// Rebind button waiting for a key, if any
+#[derive(Resource, Default)]
+struct Rebinding(Option<RebindButton>);
+
 // This system handles changing all buttons color based on mouse interaction
 fn button_system(
     mut interaction_query: Query<
@@ -76,16 +569,205 @@ fn button_system(
     }
 }
 
-fn enter_menu_state(mut menu_state: ResMut<NextState<MenuState>>) {
-    menu_state.set(MenuState::Main);
+// Selects the pressed option in place of the other options in its row
+fn option_button<T: MenuOption>(
+    interaction_query: Query<(&Interaction, &T, Entity), (Changed<Interaction>, With<Button>)>,
+    mut selected_query: Query<(Entity, &T, &mut BackgroundColor), With<SelectedOption>>,
+    mut commands: Commands,
+    mut target: ResMut<T::Target>,
+) {
+    for (interaction, option, entity) in &interaction_query {
+        if *interaction != Interaction::Pressed || selected_query.contains(entity) {
+            continue;
+        }
+        for (previous, previous_option, mut color) in &mut selected_query {
+            if previous_option.same_row(option) {
+                *color = NORMAL_BUTTON.into();
+                commands.entity(previous).remove::<SelectedOption>();
+            }
+        }
+        commands.entity(entity).insert(SelectedOption);
+        option.apply(&mut target);
+    }
+}
+
+// Sets the volume from where the slider is pressed, and sizes every slider to its volume
+fn volume_slider(
+    slider_query: Query<(
+        &Interaction,
+        &RelativeCursorPosition,
+        &VolumeSlider,
+        &Children,
+    )>,
+    mut fill_query: Query<&mut Style, With<SliderFill>>,
+    mut settings: ResMut<Settings>,
+) {
+    for (interaction, cursor, slider, children) in &slider_query {
+        if let (Interaction::Pressed, Some(position)) = (interaction, cursor.normalized) {
+            let volume = position.x.clamp(0., 1.);
+            if slider.value(&settings) != volume {
+                match slider {
+                    VolumeSlider::Master => settings.master_volume = volume,
+                    VolumeSlider::Effects => settings.effects_volume = volume,
+                }
+            }
+        }
+        let mut fills = fill_query.iter_many_mut(children);
+        while let Some(mut style) = fills.fetch_next() {
+            style.width = Val::Percent(slider.value(&settings) * 100.);
+        }
+    }
+}
+
+// Waits for a key once a rebind button is pressed, and binds it unless it is taken
+fn rebind_keys(
+    interaction_query: Query<(&Interaction, &RebindButton), Changed<Interaction>>,
+    button_query: Query<(&RebindButton, &Children)>,
+    mut key_query: Query<&mut Text, With<RebindKey>>,
+    mut status_query: Query<&mut Text, (With<RebindStatus>, Without<RebindKey>)>,
+    mut keyboard_input: ResMut<ButtonInput<KeyCode>>,
+    mut input_map: ResMut<InputMap>,
+    mut rebinding: ResMut<Rebinding>,
+) {
+    let mut status = None;
+    for (interaction, &button) in &interaction_query {
+        if *interaction == Interaction::Pressed {
+            // pressing the waiting button again leaves its key as it was
+            rebinding.0 = (rebinding.0 != Some(button)).then_some(button);
+            status = Some(String::new());
+        }
+    }
+
+    let pressed = keyboard_input.get_just_pressed().next().copied();
+    if let (Some(button), Some(key)) = (rebinding.0, pressed) {
+        // the key is bound, it doesn't also pause or close the game
+        keyboard_input.clear_just_pressed(key);
+        match input_map.rebind(button.player, button.action, key) {
+            Ok(()) => {
+                rebinding.0 = None;
+                status = Some(String::new());
+            }
+            Err(Conflict::Bound(player, action)) => {
+                status = Some(format!(
+                    "{} is already Player {:?} {}",
+                    key_name(key),
+                    player,
+                    action.label_for(player)
+                ));
+            }
+            Err(Conflict::Reserved) => {
+                status = Some(format!("{} can't be bound", key_name(key)));
+            }
+        }
+    }
+
+    if let Some(status) = status {
+        for mut text in &mut status_query {
+            text.sections[0].value = status.clone();
+        }
+    }
+    if rebinding.is_changed() {
+        for (button, children) in &button_query {
+            let mut keys = key_query.iter_many_mut(children);
+            while let Some(mut text) = keys.fetch_next() {
+                text.sections[0].value = if rebinding.0 == Some(*button) {
+                    "...".to_string()
+                } else {
+                    key_name(input_map.key(button.player, button.action))
+                };
+            }
+        }
+    }
+}
+
+fn stop_rebinding(mut rebinding: ResMut<Rebinding>) {
+    rebinding.0 = None;
+}
+
+// Spawns a labelled row with a button for each of `options`, the current one selected
+fn spawn_option_row<T: MenuOption>(
+    parent: &mut ChildBuilder,
+    label: &str,
+    options: &[T],
+    target: &T::Target,
+) {
+    let option_style = Style {
+        width: Val::Px(130.0),
+        height: Val::Px(50.0),
+        // rows stay close together for ten rules to fit the default window
+        margin: UiRect::axes(Val::Px(8.0), Val::Px(4.0)),
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::Center,
+        ..default()
+    };
+    let option_text_style = TextStyle {
+        font_size: 24.0,
+        color: TEXT_COLOR,
+        ..default()
+    };
+
+    parent
+        .spawn(NodeBundle {
+            style: Style {
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            parent.spawn(
+                TextBundle::from_section(label, option_text_style.clone()).with_style(Style {
+                    width: Val::Px(220.0),
+                    margin: UiRect::all(Val::Px(8.0)),
+                    ..default()
+                }),
+            );
+            for &option in options {
+                let mut entity = parent.spawn((
+                    ButtonBundle {
+                        style: option_style.clone(),
+                        background_color: NORMAL_BUTTON.into(),
+                        ..default()
+                    },
+                    option,
+                ));
+                entity.with_children(|parent| {
+                    parent.spawn(TextBundle::from_section(
+                        option.label(),
+                        option_text_style.clone(),
+                    ));
+                });
+                if option.is_selected(target) {
+                    entity.insert(SelectedOption);
+                }
+            }
+        });
+}
+
+// Tournament matches come back to the bracket
+fn enter_menu_state(
+    mut commands: Commands,
+    mut menu_state: ResMut<NextState<MenuState>>,
+    playing: Option<Res<TournamentMatch>>,
+) {
+    if playing.is_some() {
+        commands.remove_resource::<TournamentMatch>();
+        menu_state.set(MenuState::Tournament);
+    } else {
+        menu_state.set(MenuState::Main);
+    }
+}
+
+fn exit_menu_state(mut menu_state: ResMut<NextState<MenuState>>) {
+    menu_state.set(MenuState::Disabled);
 }
 
 fn setup_menu(mut commands: Commands, asset_server: ResMut<AssetServer>) {
-    // Common style for all buttons on the screen
+    // Common style for all buttons on the screen, small enough for all of them to fit
     let button_style = Style {
         width: Val::Px(350.0),
         height: Val::Px(65.0),
-        margin: UiRect::all(Val::Px(20.0)),
+        margin: UiRect::all(Val::Px(10.0)),
         justify_content: JustifyContent::Center,
         align_items: AlignItems::Center,
         ..default()
@@ -141,7 +823,7 @@ fn setup_menu(mut commands: Commands, asset_server: ResMut<AssetServer>) {
                             },
                         )
                         .with_style(Style {
-                            margin: UiRect::all(Val::Px(50.0)),
+                            margin: UiRect::all(Val::Px(30.0)),
                             ..default()
                         }),
                     );
@@ -149,6 +831,9 @@ fn setup_menu(mut commands: Commands, asset_server: ResMut<AssetServer>) {
                     // Display three buttons for each action available from the main menu:
                     // - Play vs Ai
                     // - Multiplayer Options
+                    // - Replays
+                    // - Stats
+                    // - Settings
                     // - Quit
                     parent
                         .spawn((
@@ -181,7 +866,7 @@ fn setup_menu(mut commands: Commands, asset_server: ResMut<AssetServer>) {
                             MenuButtonAction::Multiplayer,
                         ))
                         .with_children(|parent| {
-                            let icon = asset_server.load("textures/Game Icons/wrench.png");
+                            let icon = asset_server.load("textures/Game Icons/right.png");
                             parent.spawn(ImageBundle {
                                 style: button_icon_style.clone(),
                                 image: UiImage::new(icon),
@@ -195,58 +880,1522 @@ fn setup_menu(mut commands: Commands, asset_server: ResMut<AssetServer>) {
                     parent
                         .spawn((
                             ButtonBundle {
-                                style: button_style,
+                                style: button_style.clone(),
                                 background_color: NORMAL_BUTTON.into(),
                                 ..default()
                             },
-                            MenuButtonAction::Quit,
+                            MenuButtonAction::Replays,
                         ))
                         .with_children(|parent| {
-                            let icon = asset_server.load("textures/Game Icons/exitRight.png");
+                            let icon = asset_server.load("textures/Game Icons/right.png");
                             parent.spawn(ImageBundle {
-                                style: button_icon_style,
+                                style: button_icon_style.clone(),
                                 image: UiImage::new(icon),
                                 ..default()
                             });
-                            parent.spawn(TextBundle::from_section("Quit", button_text_style));
+                            parent.spawn(TextBundle::from_section(
+                                "Replays",
+                                button_text_style.clone(),
+                            ));
                         });
-                });
-        });
-}
-
-fn setup_menu_multiplayer(mut commands: Commands) {
-    let button_style = Style {
-        width: Val::Px(200.0),
-        height: Val::Px(65.0),
-        margin: UiRect::all(Val::Px(20.0)),
-        justify_content: JustifyContent::Center,
-        align_items: AlignItems::Center,
-        ..default()
-    };
-
-    let button_text_style = TextStyle {
-        font_size: 40.0,
-        color: TEXT_COLOR,
-        ..default()
-    };
-
-    commands
-        .spawn((
-            NodeBundle {
-                style: Style {
-                    width: Val::Percent(100.0),
-                    height: Val::Percent(100.0),
-                    align_items: AlignItems::Center,
-                    justify_content: JustifyContent::Center,
-                    ..default()
-                },
-                ..default()
-            },
-            OnMultiplayerMenuScreen,
-        ))
-        .with_children(|parent| {
-            parent
-                .spawn(NodeBundle {
+                    parent
+                        .spawn((
+                            ButtonBundle {
+                                style: button_style.clone(),
+                                background_color: NORMAL_BUTTON.into(),
+                                ..default()
+                            },
+                            MenuButtonAction::Stats,
+                        ))
+                        .with_children(|parent| {
+                            let icon = asset_server.load("textures/Game Icons/right.png");
+                            parent.spawn(ImageBundle {
+                                style: button_icon_style.clone(),
+                                image: UiImage::new(icon),
+                                ..default()
+                            });
+                            parent.spawn(TextBundle::from_section(
+                                "Stats",
+                                button_text_style.clone(),
+                            ));
+                        });
+                    parent
+                        .spawn((
+                            ButtonBundle {
+                                style: button_style.clone(),
+                                background_color: NORMAL_BUTTON.into(),
+                                ..default()
+                            },
+                            MenuButtonAction::Settings,
+                        ))
+                        .with_children(|parent| {
+                            let icon = asset_server.load("textures/Game Icons/wrench.png");
+                            parent.spawn(ImageBundle {
+                                style: button_icon_style.clone(),
+                                image: UiImage::new(icon),
+                                ..default()
+                            });
+                            parent.spawn(TextBundle::from_section(
+                                "Settings",
+                                button_text_style.clone(),
+                            ));
+                        });
+                    parent
+                        .spawn((
+                            ButtonBundle {
+                                style: button_style,
+                                background_color: NORMAL_BUTTON.into(),
+                                ..default()
+                            },
+                            MenuButtonAction::Quit,
+                        ))
+                        .with_children(|parent| {
+                            let icon = asset_server.load("textures/Game Icons/exitRight.png");
+                            parent.spawn(ImageBundle {
+                                style: button_icon_style,
+                                image: UiImage::new(icon),
+                                ..default()
+                            });
+                            parent.spawn(TextBundle::from_section("Quit", button_text_style));
+                        });
+                });
+        });
+}
+
+fn setup_menu_vs_ai(mut commands: Commands) {
+    let button_style = Style {
+        width: Val::Px(200.0),
+        height: Val::Px(65.0),
+        margin: UiRect::all(Val::Px(20.0)),
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::Center,
+        ..default()
+    };
+
+    let button_text_style = TextStyle {
+        font_size: 40.0,
+        color: TEXT_COLOR,
+        ..default()
+    };
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                ..default()
+            },
+            OnVsAiMenuScreen,
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Column,
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    background_color: Color::ALICE_BLUE.into(),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    for (action, text) in [
+                        (MenuButtonAction::StartVsAi(AiDifficulty::Easy), "Easy"),
+                        (MenuButtonAction::StartVsAi(AiDifficulty::Normal), "Normal"),
+                        (MenuButtonAction::StartVsAi(AiDifficulty::Hard), "Hard"),
+                        (
+                            MenuButtonAction::StartVsAi(AiDifficulty::Perfect),
+                            "Perfect",
+                        ),
+                        (MenuButtonAction::BackToMenu, "Back"),
+                    ] {
+                        parent
+                            .spawn((
+                                ButtonBundle {
+                                    style: button_style.clone(),
+                                    background_color: NORMAL_BUTTON.into(),
+                                    ..default()
+                                },
+                                action,
+                            ))
+                            .with_children(|parent| {
+                                parent.spawn(TextBundle::from_section(
+                                    text,
+                                    button_text_style.clone(),
+                                ));
+                            });
+                    }
+                });
+        });
+}
+
+fn setup_menu_multiplayer(mut commands: Commands) {
+    let button_style = Style {
+        width: Val::Px(200.0),
+        height: Val::Px(65.0),
+        margin: UiRect::all(Val::Px(20.0)),
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::Center,
+        ..default()
+    };
+
+    let button_text_style = TextStyle {
+        font_size: 40.0,
+        color: TEXT_COLOR,
+        ..default()
+    };
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                ..default()
+            },
+            OnMultiplayerMenuScreen,
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Column,
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    background_color: Color::ALICE_BLUE.into(),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    for (action, text) in [
+                        (MenuButtonAction::MultiplayerLocal, "Play Local"),
+                        (MenuButtonAction::MultiplayerFourPlayer, "4 Players"),
+                        (MenuButtonAction::MultiplayerDoubles, "Doubles"),
+                        (MenuButtonAction::MultiplayerNetwork, "Play Network"),
+                        (MenuButtonAction::Tournament, "Tournament"),
+                        (MenuButtonAction::BackToMenu, "Back"),
+                    ] {
+                        parent
+                            .spawn((
+                                ButtonBundle {
+                                    style: button_style.clone(),
+                                    background_color: NORMAL_BUTTON.into(),
+                                    ..default()
+                                },
+                                action,
+                            ))
+                            .with_children(|parent| {
+                                parent.spawn(TextBundle::from_section(
+                                    text,
+                                    button_text_style.clone(),
+                                ));
+                            });
+                    }
+                });
+        });
+}
+
+fn replay_label(modified: SystemTime, points: usize) -> String {
+    let age = SystemTime::now()
+        .duration_since(modified)
+        .unwrap_or_default()
+        .as_secs();
+    let age = match age {
+        0..=59 => "just now".to_string(),
+        60..=3599 => format!("{} min ago", age / 60),
+        3600..=86399 => format!("{} h ago", age / 3600),
+        _ => format!("{} days ago", age / 86400),
+    };
+    format!("{}  |  {} points", age, points)
+}
+
+fn setup_menu_tournament_entry(mut commands: Commands, entry: Res<TournamentEntry>) {
+    let button_style = Style {
+        width: Val::Px(250.0),
+        height: Val::Px(65.0),
+        margin: UiRect::all(Val::Px(20.0)),
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::Center,
+        ..default()
+    };
+
+    let button_text_style = TextStyle {
+        font_size: 40.0,
+        color: TEXT_COLOR,
+        ..default()
+    };
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                ..default()
+            },
+            OnTournamentEntryMenuScreen,
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Column,
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    background_color: Color::ALICE_BLUE.into(),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    parent.spawn((
+                        TextBundle::from_section(
+                            tournament_names_text(&entry),
+                            TextStyle {
+                                font_size: 24.0,
+                                color: TEXT_COLOR,
+                                ..default()
+                            },
+                        )
+                        .with_style(Style {
+                            max_width: Val::Px(900.0),
+                            margin: UiRect::all(Val::Px(20.0)),
+                            ..default()
+                        }),
+                        TournamentNamesText,
+                    ));
+
+                    spawn_option_row(
+                        parent,
+                        "Elimination",
+                        &[
+                            TournamentFormatOption(BracketFormat::SingleElimination),
+                            TournamentFormatOption(BracketFormat::DoubleElimination),
+                        ],
+                        &entry,
+                    );
+
+                    parent.spawn(NodeBundle::default()).with_children(|parent| {
+                        for (action, text) in [
+                            (MenuButtonAction::AddProfilePlayers, "Add Profiles"),
+                            (MenuButtonAction::StartTournament, "Start"),
+                            (MenuButtonAction::Multiplayer, "Back"),
+                        ] {
+                            parent
+                                .spawn((
+                                    ButtonBundle {
+                                        style: button_style.clone(),
+                                        background_color: NORMAL_BUTTON.into(),
+                                        ..default()
+                                    },
+                                    action,
+                                ))
+                                .with_children(|parent| {
+                                    parent.spawn(TextBundle::from_section(
+                                        text,
+                                        button_text_style.clone(),
+                                    ));
+                                });
+                        }
+                    });
+                });
+        });
+}
+
+fn tournament_names_text(entry: &TournamentEntry) -> String {
+    let names = if entry.names.is_empty() {
+        "none yet".to_string()
+    } else {
+        entry.names.join(", ")
+    };
+    let status = match &entry.error {
+        Some(error) => error.as_str(),
+        None => "Enter to add, Backspace to remove",
+    };
+    format!(
+        "Players ({}): {}\nName: {}_  ({})",
+        entry.names.len(),
+        names,
+        entry.typed,
+        status
+    )
+}
+
+// Types the names of the players of a new tournament, in seeding order
+fn enter_tournament_names(
+    mut entry: ResMut<TournamentEntry>,
+    mut characters: EventReader<ReceivedCharacter>,
+    mut keyboard_input: ResMut<ButtonInput<KeyCode>>,
+    mut text_query: Query<&mut Text, With<TournamentNamesText>>,
+) {
+    let entry = entry.as_mut();
+    for event in characters.read() {
+        for c in event.char.chars().filter(|c| !c.is_control()) {
+            if entry.typed.chars().count() < MAX_NAME_LEN {
+                entry.typed.push(c);
+                entry.error = None;
+            }
+        }
+    }
+    if keyboard_input.just_pressed(KeyCode::Backspace) {
+        // a name being typed loses its last letter, otherwise the last player is removed
+        if entry.typed.pop().is_none() {
+            entry.names.pop();
+        }
+        entry.error = None;
+    }
+
+    if keyboard_input.just_pressed(KeyCode::Enter) {
+        let name = entry.typed.trim();
+        if name.is_empty() {
+            entry.error = Some("type a name first".to_string());
+        } else if entry.names.iter().any(|other| other == name) {
+            entry.error = Some("that player is already in".to_string());
+        } else if entry.names.len() == MAX_PLAYERS {
+            entry.error = Some(format!("{} players at most", MAX_PLAYERS));
+        } else {
+            entry.names.push(name.to_string());
+            entry.typed.clear();
+        }
+    }
+    if keyboard_input.just_pressed(KeyCode::Escape) && !entry.typed.is_empty() {
+        // it drops the name, it doesn't also close the game
+        keyboard_input.clear_just_pressed(KeyCode::Escape);
+        entry.typed.clear();
+    }
+
+    for mut text in &mut text_query {
+        text.sections[0].value = tournament_names_text(entry);
+    }
+}
+
+fn setup_menu_tournament(
+    mut commands: Commands,
+    tournament: Res<Tournament>,
+    view: Res<BracketView>,
+) {
+    let button_style = Style {
+        width: Val::Px(300.0),
+        height: Val::Px(50.0),
+        margin: UiRect::all(Val::Px(8.0)),
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::Center,
+        ..default()
+    };
+
+    let button_text_style = TextStyle {
+        font_size: 32.0,
+        color: TEXT_COLOR,
+        ..default()
+    };
+
+    let next_match = tournament.next_match();
+    let title = match (
+        next_match.and_then(|index| tournament.opponents(index)),
+        tournament.champion(),
+    ) {
+        (Some((a, b)), _) => format!("Next match: {} vs {}", a, b),
+        (None, Some(champion)) => format!("{} wins the tournament!", champion),
+        (None, None) => String::new(),
+    };
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                ..default()
+            },
+            OnTournamentMenuScreen,
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Column,
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    background_color: Color::ALICE_BLUE.into(),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    parent.spawn(TextBundle::from_section(
+                        title,
+                        TextStyle {
+                            font_size: 30.0,
+                            color: TEXT_COLOR,
+                            ..default()
+                        },
+                    ));
+
+                    if tournament.format == BracketFormat::DoubleElimination {
+                        spawn_option_row(
+                            parent,
+                            "Bracket",
+                            &[
+                                BracketViewOption(Bracket::Winners),
+                                BracketViewOption(Bracket::Losers),
+                            ],
+                            &view,
+                        );
+                    }
+
+                    parent
+                        .spawn((
+                            NodeBundle {
+                                style: Style {
+                                    height: Val::Px(500.0),
+                                    ..default()
+                                },
+                                ..default()
+                            },
+                            BracketColumns,
+                        ))
+                        .with_children(|parent| spawn_bracket_columns(parent, &tournament, view.0));
+
+                    parent.spawn(NodeBundle::default()).with_children(|parent| {
+                        let mut buttons = vec![];
+                        if next_match.is_some() {
+                            buttons.push((MenuButtonAction::PlayTournamentMatch, "Play Match"));
+                            buttons.push((MenuButtonAction::EndTournament, "Abandon"));
+                        } else {
+                            buttons.push((MenuButtonAction::EndTournament, "New Tournament"));
+                        }
+                        buttons.push((MenuButtonAction::Multiplayer, "Back"));
+                        for (action, text) in buttons {
+                            parent
+                                .spawn((
+                                    ButtonBundle {
+                                        style: button_style.clone(),
+                                        background_color: NORMAL_BUTTON.into(),
+                                        ..default()
+                                    },
+                                    action,
+                                ))
+                                .with_children(|parent| {
+                                    parent.spawn(TextBundle::from_section(
+                                        text,
+                                        button_text_style.clone(),
+                                    ));
+                                });
+                        }
+                    });
+                });
+        });
+}
+
+// A column for each round of the bracket shown, its matches spread down the column so each
+// sits between the two it follows from
+fn spawn_bracket_columns(parent: &mut ChildBuilder, tournament: &Tournament, view: Bracket) {
+    let shown = |bracket: Bracket| (bracket == Bracket::Losers) == (view == Bracket::Losers);
+    let mut rounds: Vec<(Bracket, usize)> = tournament
+        .matches
+        .iter()
+        .filter(|match_| shown(match_.bracket))
+        .map(|match_| (match_.bracket, match_.round))
+        .collect();
+    rounds.sort_by_key(|&(bracket, round)| (bracket == Bracket::GrandFinal, round));
+    rounds.dedup();
+    let last_round = |bracket: Bracket| {
+        rounds
+            .iter()
+            .filter(|(other, _)| *other == bracket)
+            .map(|&(_, round)| round)
+            .max()
+    };
+    let header_style = TextStyle {
+        font_size: 16.0,
+        color: TEXT_COLOR,
+        ..default()
+    };
+    let next_match = tournament.next_match();
+
+    for &(bracket, round) in &rounds {
+        let is_last = last_round(bracket) == Some(round);
+        let header = match (bracket, tournament.format) {
+            (Bracket::GrandFinal, _) => "Grand Final".to_string(),
+            (Bracket::Winners, BracketFormat::SingleElimination) if is_last => "Final".to_string(),
+            (Bracket::Winners, _) if is_last => "Winners Final".to_string(),
+            (Bracket::Winners, _) => format!("Round {}", round + 1),
+            (Bracket::Losers, _) if is_last => "Losers Final".to_string(),
+            (Bracket::Losers, _) => format!("Losers {}", round + 1),
+        };
+        parent
+            .spawn(NodeBundle {
+                style: Style {
+                    width: Val::Px(140.0),
+                    margin: UiRect::horizontal(Val::Px(3.0)),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                ..default()
+            })
+            .with_children(|parent| {
+                parent.spawn(TextBundle::from_section(header, header_style.clone()));
+                parent
+                    .spawn(NodeBundle {
+                        style: Style {
+                            flex_grow: 1.0,
+                            flex_direction: FlexDirection::Column,
+                            justify_content: JustifyContent::SpaceAround,
+                            ..default()
+                        },
+                        ..default()
+                    })
+                    .with_children(|parent| {
+                        for (index, match_) in tournament.matches.iter().enumerate() {
+                            if (match_.bracket, match_.round) == (bracket, round) {
+                                spawn_bracket_match(
+                                    parent,
+                                    tournament,
+                                    index,
+                                    next_match == Some(index),
+                                );
+                            }
+                        }
+                    });
+            });
+    }
+}
+
+// The two players of a match, the winner in the text color
+fn spawn_bracket_match(
+    parent: &mut ChildBuilder,
+    tournament: &Tournament,
+    index: usize,
+    is_next: bool,
+) {
+    let outcome = tournament.outcome(index);
+    parent
+        .spawn(NodeBundle {
+            style: Style {
+                width: Val::Px(140.0),
+                flex_direction: FlexDirection::Column,
+                padding: UiRect::all(Val::Px(1.0)),
+                margin: UiRect::vertical(Val::Px(1.0)),
+                ..default()
+            },
+            background_color: if is_next {
+                HOVERED_PRESSED_BUTTON.into()
+            } else {
+                NORMAL_BUTTON.into()
+            },
+            ..default()
+        })
+        .with_children(|parent| {
+            for slot in tournament.matches[index].slots {
+                let (name, color) = match tournament.entrant(slot) {
+                    Some(Entrant::Player(player)) => {
+                        let color = match outcome {
+                            Some((Entrant::Player(winner), _)) if winner == player => TEXT_COLOR,
+                            Some(_) => Color::GRAY,
+                            None => Color::WHITE,
+                        };
+                        (tournament.players[player].as_str(), color)
+                    }
+                    Some(Entrant::Bye) => ("bye", Color::DARK_GRAY),
+                    None => ("...", Color::DARK_GRAY),
+                };
+                parent.spawn(TextBundle::from_section(
+                    name,
+                    TextStyle {
+                        font_size: 11.0,
+                        color,
+                        ..default()
+                    },
+                ));
+            }
+        });
+}
+
+fn update_bracket(
+    mut commands: Commands,
+    tournament: Res<Tournament>,
+    view: Res<BracketView>,
+    columns_query: Query<Entity, With<BracketColumns>>,
+) {
+    if !tournament.is_changed() && !view.is_changed() {
+        return;
+    }
+    for columns in &columns_query {
+        commands
+            .entity(columns)
+            .despawn_descendants()
+            .with_children(|parent| spawn_bracket_columns(parent, &tournament, view.0));
+    }
+}
+
+// Lists the most recent recorded matches, newest first
+fn setup_menu_replays(mut commands: Commands, dir: Option<Res<ReplayDir>>) {
+    let button_style = Style {
+        width: Val::Px(500.0),
+        height: Val::Px(50.0),
+        margin: UiRect::all(Val::Px(10.0)),
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::Center,
+        ..default()
+    };
+    let button_text_style = TextStyle {
+        font_size: 24.0,
+        color: TEXT_COLOR,
+        ..default()
+    };
+
+    let replays: Vec<_> = dir
+        .map(|dir| dir.list())
+        .unwrap_or_default()
+        .into_iter()
+        .take(LISTED_REPLAYS)
+        .filter_map(|(path, modified)| {
+            let points = load_replay(&path)?.rounds.len();
+            Some((path, replay_label(modified, points)))
+        })
+        .collect();
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                ..default()
+            },
+            OnReplaysMenuScreen,
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Column,
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    background_color: Color::ALICE_BLUE.into(),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    if replays.is_empty() {
+                        parent.spawn(
+                            TextBundle::from_section(
+                                "No matches recorded yet",
+                                button_text_style.clone(),
+                            )
+                            .with_style(Style {
+                                margin: UiRect::all(Val::Px(20.0)),
+                                ..default()
+                            }),
+                        );
+                    }
+                    for (path, label) in replays {
+                        parent
+                            .spawn((
+                                ButtonBundle {
+                                    style: button_style.clone(),
+                                    background_color: NORMAL_BUTTON.into(),
+                                    ..default()
+                                },
+                                MenuButtonAction::PlayReplay(path),
+                            ))
+                            .with_children(|parent| {
+                                parent.spawn(TextBundle::from_section(
+                                    label,
+                                    button_text_style.clone(),
+                                ));
+                            });
+                    }
+                    parent
+                        .spawn((
+                            ButtonBundle {
+                                style: button_style.clone(),
+                                background_color: NORMAL_BUTTON.into(),
+                                ..default()
+                            },
+                            MenuButtonAction::BackToMenu,
+                        ))
+                        .with_children(|parent| {
+                            parent.spawn(TextBundle::from_section("Back", button_text_style));
+                        });
+                });
+        });
+}
+
+fn setup_menu_multiplayer_network(mut commands: Commands) {
+    let button_style = Style {
+        width: Val::Px(200.0),
+        height: Val::Px(65.0),
+        margin: UiRect::all(Val::Px(20.0)),
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::Center,
+        ..default()
+    };
+
+    let button_text_style = TextStyle {
+        font_size: 40.0,
+        color: TEXT_COLOR,
+        ..default()
+    };
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                ..default()
+            },
+            OnMultiplayerNetworkMenuScreen,
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Column,
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    background_color: Color::ALICE_BLUE.into(),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    for (action, text) in [
+                        (MenuButtonAction::HostNetwork, "Host"),
+                        (MenuButtonAction::JoinNetwork, "Join"),
+                        (MenuButtonAction::BackToMenu, "Back"),
+                    ] {
+                        parent
+                            .spawn((
+                                ButtonBundle {
+                                    style: button_style.clone(),
+                                    background_color: NORMAL_BUTTON.into(),
+                                    ..default()
+                                },
+                                action,
+                            ))
+                            .with_children(|parent| {
+                                parent.spawn(TextBundle::from_section(
+                                    text,
+                                    button_text_style.clone(),
+                                ));
+                            });
+                    }
+
+                    parent.spawn((
+                        TextBundle::from_section(
+                            "",
+                            TextStyle {
+                                font_size: 24.0,
+                                color: TEXT_COLOR,
+                                ..default()
+                            },
+                        )
+                        .with_style(Style {
+                            margin: UiRect::all(Val::Px(20.0)),
+                            ..default()
+                        }),
+                        NetworkStatusText,
+                    ));
+
+                    parent.spawn(TextBundle::from_section(
+                        "Games on your network",
+                        TextStyle {
+                            font_size: 24.0,
+                            color: TEXT_COLOR,
+                            ..default()
+                        },
+                    ));
+                    parent.spawn((
+                        NodeBundle {
+                            style: Style {
+                                flex_direction: FlexDirection::Column,
+                                align_items: AlignItems::Center,
+                                min_height: Val::Px(105.0),
+                                ..default()
+                            },
+                            ..default()
+                        },
+                        LanGameList,
+                    ));
+                });
+        });
+}
+
+// Shows which gamepad, or which AI in doubles, steers each player of the match
+#[derive(Component)]
+struct JoinSlot(Player);
+
+fn join_slot_text(
+    player: Player,
+    match_mode: &MatchMode,
+    assignment: &GamepadAssignment,
+    gamepads: &Gamepads,
+) -> String {
+    let ai = match match_mode {
+        MatchMode::Doubles(seats) => seats[player.index()],
+        _ => None,
+    };
+    let controller = match (ai, assignment.gamepad(player)) {
+        (Some(difficulty), _) => format!("AI {:?}", difficulty),
+        (_, Some(gamepad)) => gamepads
+            .name(gamepad)
+            .map_or_else(|| format!("Gamepad {}", gamepad.id), str::to_string),
+        (_, None) => "Keyboard".to_string(),
+    };
+    match match_mode {
+        MatchMode::Doubles(_) => format!(
+            "Player {:?} ({}): {}",
+            player,
+            match_mode.side_name(Seat(player).side()),
+            controller
+        ),
+        _ => format!("Player {:?}: {}", player, controller),
+    }
+}
+
+/// Difficulty of the AI after `ai` when switching who plays a seat of a doubles match, from a
+/// person through each difficulty and back
+fn next_seat_ai(ai: Option<AiDifficulty>) -> Option<AiDifficulty> {
+    match ai {
+        None => Some(AiDifficulty::Easy),
+        Some(AiDifficulty::Easy) => Some(AiDifficulty::Normal),
+        Some(AiDifficulty::Normal) => Some(AiDifficulty::Hard),
+        Some(AiDifficulty::Hard) => Some(AiDifficulty::Perfect),
+        Some(AiDifficulty::Perfect) => None,
+    }
+}
+
+fn setup_menu_join(
+    mut commands: Commands,
+    match_mode: Res<MatchMode>,
+    assignment: Res<GamepadAssignment>,
+    gamepads: Res<Gamepads>,
+    profiles: Res<Profiles>,
+) {
+    let button_style = Style {
+        width: Val::Px(200.0),
+        height: Val::Px(65.0),
+        margin: UiRect::all(Val::Px(20.0)),
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::Center,
+        ..default()
+    };
+
+    let button_text_style = TextStyle {
+        font_size: 40.0,
+        color: TEXT_COLOR,
+        ..default()
+    };
+    let text_style = TextStyle {
+        font_size: 24.0,
+        color: TEXT_COLOR,
+        ..default()
+    };
+
+    // the AI needs no gamepad
+    let players = match *match_mode {
+        MatchMode::VsAi(_) => &[Player::A],
+        mode => mode.players(),
+    };
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                ..default()
+            },
+            OnJoinMenuScreen,
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Column,
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    background_color: Color::ALICE_BLUE.into(),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    for &player in players {
+                        parent.spawn((
+                            TextBundle::from_section(
+                                join_slot_text(player, &match_mode, &assignment, &gamepads),
+                                button_text_style.clone(),
+                            )
+                            .with_style(Style {
+                                margin: UiRect::all(Val::Px(8.0)),
+                                ..default()
+                            }),
+                            JoinSlot(player),
+                        ));
+                        if let MatchMode::Doubles(_) = *match_mode {
+                            parent
+                                .spawn((
+                                    ButtonBundle {
+                                        style: Style {
+                                            width: Val::Px(400.0),
+                                            height: Val::Px(40.0),
+                                            margin: UiRect::bottom(Val::Px(4.0)),
+                                            justify_content: JustifyContent::Center,
+                                            align_items: AlignItems::Center,
+                                            ..default()
+                                        },
+                                        background_color: NORMAL_BUTTON.into(),
+                                        ..default()
+                                    },
+                                    MenuButtonAction::SwitchSeatAi(player),
+                                ))
+                                .with_children(|parent| {
+                                    parent.spawn(TextBundle::from_section(
+                                        "Switch person / AI",
+                                        text_style.clone(),
+                                    ));
+                                });
+                        }
+                        // the extra players of a four-player or doubles match play as guests
+                        if !matches!(player, Player::A | Player::B) {
+                            continue;
+                        }
+                        parent
+                            .spawn((
+                                ButtonBundle {
+                                    style: Style {
+                                        width: Val::Px(400.0),
+                                        height: Val::Px(40.0),
+                                        justify_content: JustifyContent::Center,
+                                        align_items: AlignItems::Center,
+                                        ..default()
+                                    },
+                                    background_color: NORMAL_BUTTON.into(),
+                                    ..default()
+                                },
+                                MenuButtonAction::PickProfile(player),
+                            ))
+                            .with_children(|parent| {
+                                parent.spawn((
+                                    TextBundle::from_section(
+                                        profile_slot_text(player, &profiles),
+                                        text_style.clone(),
+                                    ),
+                                    ProfileSlot(player),
+                                ));
+                            });
+                    }
+                    parent.spawn(
+                        TextBundle::from_section(
+                            "Press A on a gamepad to join, B to leave",
+                            text_style.clone(),
+                        )
+                        .with_style(Style {
+                            margin: UiRect::all(Val::Px(8.0)),
+                            ..default()
+                        }),
+                    );
+
+                    parent.spawn(NodeBundle::default()).with_children(|parent| {
+                        for (action, text) in [
+                            (MenuButtonAction::PickMatchRules, "Next"),
+                            (MenuButtonAction::BackToMenu, "Back"),
+                        ] {
+                            parent
+                                .spawn((
+                                    ButtonBundle {
+                                        style: button_style.clone(),
+                                        background_color: NORMAL_BUTTON.into(),
+                                        ..default()
+                                    },
+                                    action,
+                                ))
+                                .with_children(|parent| {
+                                    parent.spawn(TextBundle::from_section(
+                                        text,
+                                        button_text_style.clone(),
+                                    ));
+                                });
+                        }
+                    });
+                });
+        });
+}
+
+// Gamepads join the first free player with South and leave with East
+fn join_gamepads(
+    gamepads: Res<Gamepads>,
+    match_mode: Res<MatchMode>,
+    buttons: Res<ButtonInput<GamepadButton>>,
+    mut assignment: ResMut<GamepadAssignment>,
+    mut slot_query: Query<(&mut Text, &JoinSlot)>,
+) {
+    for gamepad in gamepads.iter() {
+        if buttons.just_pressed(GamepadButton::new(gamepad, GamepadButtonType::South)) {
+            assignment.join(gamepad);
+        }
+        if buttons.just_pressed(GamepadButton::new(gamepad, GamepadButtonType::East)) {
+            assignment.leave(gamepad);
+        }
+    }
+    for (mut text, slot) in &mut slot_query {
+        text.sections[0].value = join_slot_text(slot.0, &match_mode, &assignment, &gamepads);
+    }
+}
+
+fn profile_slot_text(player: Player, profiles: &Profiles) -> String {
+    format!("Profile: {}", profiles.selected(player).unwrap_or("Guest"))
+}
+
+fn update_profile_slots(profiles: Res<Profiles>, mut slot_query: Query<(&mut Text, &ProfileSlot)>) {
+    for (mut text, slot) in &mut slot_query {
+        text.sections[0].value = profile_slot_text(slot.0, &profiles);
+    }
+}
+
+fn profile_stats_text(profile: &Profile) -> String {
+    let stats = &profile.stats;
+    let mut text = format!(
+        "{} ({:.0})  |  matches {}-{}  |  points {}-{}  |  longest rally {}  |  fastest ball {:.0}",
+        profile.name,
+        profile.rating,
+        stats.matches.won,
+        stats.matches.lost,
+        stats.points.won,
+        stats.points.lost,
+        stats.longest_rally,
+        stats.fastest_ball,
+    );
+    if !stats.vs_ai.is_empty() {
+        let records: Vec<String> = stats
+            .vs_ai
+            .iter()
+            .map(|(difficulty, record)| format!("{:?} {}-{}", difficulty, record.won, record.lost))
+            .collect();
+        text.push_str(&format!("\nvs AI: {}", records.join(", ")));
+    }
+    text
+}
+
+fn spawn_profile_rows(parent: &mut ChildBuilder, profiles: &Profiles) {
+    let text_style = TextStyle {
+        font_size: 20.0,
+        color: TEXT_COLOR,
+        ..default()
+    };
+    if profiles.profiles.is_empty() {
+        parent.spawn(TextBundle::from_section(
+            "No profiles yet",
+            text_style.clone(),
+        ));
+    }
+    for profile in &profiles.profiles {
+        parent.spawn(
+            TextBundle::from_section(profile_stats_text(profile), text_style.clone()).with_style(
+                Style {
+                    margin: UiRect::all(Val::Px(8.0)),
+                    ..default()
+                },
+            ),
+        );
+    }
+}
+
+fn setup_menu_stats(mut commands: Commands, profiles: Res<Profiles>) {
+    let button_style = Style {
+        width: Val::Px(300.0),
+        height: Val::Px(65.0),
+        margin: UiRect::all(Val::Px(20.0)),
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::Center,
+        ..default()
+    };
+
+    let button_text_style = TextStyle {
+        font_size: 40.0,
+        color: TEXT_COLOR,
+        ..default()
+    };
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                ..default()
+            },
+            OnStatsMenuScreen,
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Column,
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    background_color: Color::ALICE_BLUE.into(),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    parent
+                        .spawn((
+                            NodeBundle {
+                                style: Style {
+                                    flex_direction: FlexDirection::Column,
+                                    align_items: AlignItems::Center,
+                                    margin: UiRect::all(Val::Px(20.0)),
+                                    ..default()
+                                },
+                                ..default()
+                            },
+                            ProfileList,
+                        ))
+                        .with_children(|parent| spawn_profile_rows(parent, &profiles));
+
+                    parent.spawn((
+                        TextBundle::from_section(
+                            "",
+                            TextStyle {
+                                font_size: 24.0,
+                                color: TEXT_COLOR,
+                                ..default()
+                            },
+                        ),
+                        ProfileNameText,
+                    ));
+
+                    parent.spawn(NodeBundle::default()).with_children(|parent| {
+                        for (action, text) in [
+                            (MenuButtonAction::NewProfile, "New Profile"),
+                            (MenuButtonAction::Leaderboard, "Leaderboard"),
+                            (MenuButtonAction::BackToMenu, "Back"),
+                        ] {
+                            parent
+                                .spawn((
+                                    ButtonBundle {
+                                        style: button_style.clone(),
+                                        background_color: NORMAL_BUTTON.into(),
+                                        ..default()
+                                    },
+                                    action,
+                                ))
+                                .with_children(|parent| {
+                                    parent.spawn(TextBundle::from_section(
+                                        text,
+                                        button_text_style.clone(),
+                                    ));
+                                });
+                        }
+                    });
+                });
+        });
+}
+
+fn setup_menu_leaderboard(mut commands: Commands, profiles: Res<Profiles>) {
+    let button_style = Style {
+        width: Val::Px(300.0),
+        height: Val::Px(65.0),
+        margin: UiRect::all(Val::Px(10.0)),
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::Center,
+        ..default()
+    };
+
+    let button_text_style = TextStyle {
+        font_size: 40.0,
+        color: TEXT_COLOR,
+        ..default()
+    };
+
+    // the rating graph is drawn below, in the lower half of the window
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::FlexStart,
+                    flex_direction: FlexDirection::Column,
+                    ..default()
+                },
+                ..default()
+            },
+            OnLeaderboardMenuScreen,
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Column,
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    background_color: Color::ALICE_BLUE.into(),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    let text_style = TextStyle {
+                        font_size: 20.0,
+                        color: TEXT_COLOR,
+                        ..default()
+                    };
+                    let ranked = profiles.leaderboard();
+                    if ranked.is_empty() {
+                        parent.spawn(TextBundle::from_section(
+                            "No profiles yet",
+                            text_style.clone(),
+                        ));
+                    }
+                    for (rank, (profile, color)) in ranked.iter().zip(PROFILE_COLORS).enumerate() {
+                        let matches = &profile.stats.matches;
+                        parent.spawn(
+                            TextBundle::from_section(
+                                format!(
+                                    "{}. {}  {:.0}  ({}-{})",
+                                    rank + 1,
+                                    profile.name,
+                                    profile.rating,
+                                    matches.won,
+                                    matches.lost,
+                                ),
+                                TextStyle {
+                                    color,
+                                    ..text_style.clone()
+                                },
+                            )
+                            .with_style(Style {
+                                margin: UiRect::all(Val::Px(4.0)),
+                                ..default()
+                            }),
+                        );
+                    }
+
+                    parent.spawn((
+                        TextBundle::from_section("", text_style.clone()),
+                        ExportStatusText,
+                    ));
+
+                    parent.spawn(NodeBundle::default()).with_children(|parent| {
+                        for (action, text) in [
+                            (MenuButtonAction::ExportHistory, "Export"),
+                            (MenuButtonAction::Stats, "Back"),
+                        ] {
+                            parent
+                                .spawn((
+                                    ButtonBundle {
+                                        style: button_style.clone(),
+                                        background_color: NORMAL_BUTTON.into(),
+                                        ..default()
+                                    },
+                                    action,
+                                ))
+                                .with_children(|parent| {
+                                    parent.spawn(TextBundle::from_section(
+                                        text,
+                                        button_text_style.clone(),
+                                    ));
+                                });
+                        }
+                    });
+                });
+        });
+}
+
+// Plots the rating of the listed profiles after each of their rated matches
+fn draw_rating_graph(mut painter: ShapePainter, profiles: Res<Profiles>) {
+    let (left, right, bottom, top) = (-450., 450., -330., -40.);
+
+    let histories: Vec<Vec<f32>> = profiles
+        .leaderboard()
+        .iter()
+        .take(LISTED_PROFILES)
+        .map(|profile| profiles.rating_history(&profile.name))
+        .collect();
+    let matches = histories.iter().map(Vec::len).max().unwrap_or(0);
+    let (low, high) = histories
+        .iter()
+        .flatten()
+        .fold((START_RATING, START_RATING), |(low, high), &rating| {
+            (low.min(rating), high.max(rating))
+        });
+    // leave room above and below the lines
+    let (low, high) = (low - 20., high + 20.);
+
+    let x = |index: usize| left + (right - left) * index as f32 / (matches.max(2) - 1) as f32;
+    let y = |rating: f32| bottom + (top - bottom) * (rating - low) / (high - low);
+
+    painter.thickness = 1.;
+    painter.color = Color::WHITE;
+    painter.line(Vec3::new(left, bottom, 0.), Vec3::new(right, bottom, 0.));
+    painter.line(Vec3::new(left, bottom, 0.), Vec3::new(left, top, 0.));
+    painter.color = Color::GRAY;
+    painter.line(
+        Vec3::new(left, y(START_RATING), 0.),
+        Vec3::new(right, y(START_RATING), 0.),
+    );
+
+    painter.thickness = 3.;
+    for (history, color) in histories.iter().zip(PROFILE_COLORS) {
+        painter.color = color;
+        for (index, pair) in history.windows(2).enumerate() {
+            painter.line(
+                Vec3::new(x(index), y(pair[0]), 1.),
+                Vec3::new(x(index + 1), y(pair[1]), 1.),
+            );
+        }
+    }
+}
+
+fn update_profile_list(
+    mut commands: Commands,
+    profiles: Res<Profiles>,
+    list_query: Query<Entity, With<ProfileList>>,
+) {
+    if !profiles.is_changed() {
+        return;
+    }
+    for list in &list_query {
+        commands
+            .entity(list)
+            .despawn_descendants()
+            .with_children(|parent| spawn_profile_rows(parent, &profiles));
+    }
+}
+
+// Types the name of a new profile once New Profile is pressed. Enter creates it, escape gives up.
+fn enter_profile_name(
+    mut entry: ResMut<ProfileNameEntry>,
+    mut characters: EventReader<ReceivedCharacter>,
+    mut keyboard_input: ResMut<ButtonInput<KeyCode>>,
+    mut profiles: ResMut<Profiles>,
+    mut text_query: Query<&mut Text, With<ProfileNameText>>,
+) {
+    let ProfileNameEntry { name, error } = entry.as_mut();
+    let Some(typed) = name else {
+        characters.clear();
+        return;
+    };
+    for event in characters.read() {
+        for c in event.char.chars().filter(|c| !c.is_control()) {
+            if typed.chars().count() < MAX_NAME_LEN {
+                typed.push(c);
+                *error = None;
+            }
+        }
+    }
+    if keyboard_input.just_pressed(KeyCode::Backspace) {
+        typed.pop();
+    }
+
+    if keyboard_input.just_pressed(KeyCode::Enter) {
+        match profiles.add(typed) {
+            Ok(()) => *name = None,
+            Err(NameError::Empty) => *error = Some("type a name first"),
+            Err(NameError::Taken) => *error = Some("that name is taken"),
+        }
+    }
+    if keyboard_input.just_pressed(KeyCode::Escape) {
+        // it stops the typing, it doesn't also close the game
+        keyboard_input.clear_just_pressed(KeyCode::Escape);
+        *name = None;
+    }
+
+    for mut text in &mut text_query {
+        text.sections[0].value = match (&name, &error) {
+            (Some(typed), Some(error)) => format!("Name: {}_  ({})", typed, error),
+            (Some(typed), None) => format!("Name: {}_  (Enter to create)", typed),
+            (None, _) => String::new(),
+        };
+    }
+}
+
+fn stop_profile_name_entry(mut entry: ResMut<ProfileNameEntry>) {
+    *entry = ProfileNameEntry::default();
+}
+
+fn setup_menu_match_rules(
+    mut commands: Commands,
+    rules: Res<MatchRules>,
+    menu_state: Res<State<MenuState>>,
+) {
+    let button_style = Style {
+        width: Val::Px(200.0),
+        height: Val::Px(65.0),
+        margin: UiRect::all(Val::Px(20.0)),
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::Center,
+        ..default()
+    };
+
+    let button_text_style = TextStyle {
+        font_size: 40.0,
+        color: TEXT_COLOR,
+        ..default()
+    };
+
+    // from the settings the rules are only changed, not played
+    let actions = if *menu_state.get() == MenuState::SettingsMatchRules {
+        vec![(MenuButtonAction::Settings, "Back")]
+    } else {
+        vec![
+            (MenuButtonAction::StartMatch, "Start"),
+            (MenuButtonAction::BackToMenu, "Back"),
+        ]
+    };
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                ..default()
+            },
+            OnMatchRulesMenuScreen,
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn(NodeBundle {
                     style: Style {
                         flex_direction: FlexDirection::Column,
                         align_items: AlignItems::Center,
@@ -256,11 +2405,472 @@ fn setup_menu_multiplayer(mut commands: Commands) {
                     ..default()
                 })
                 .with_children(|parent| {
-                    for (action, text) in [
-                        (MenuButtonAction::MultiplayerLocal, "Play Local"),
-                        (MenuButtonAction::MultiplayerNetwork, "Play Network"),
-                        (MenuButtonAction::BackToMenu, "Back"),
+                    spawn_option_row(
+                        parent,
+                        "Points to win",
+                        &[
+                            RuleOption::PointsToWin(2),
+                            RuleOption::PointsToWin(5),
+                            RuleOption::PointsToWin(11),
+                            RuleOption::PointsToWin(21),
+                        ],
+                        &rules,
+                    );
+                    spawn_option_row(
+                        parent,
+                        "Best of",
+                        &[
+                            RuleOption::BestOf(1),
+                            RuleOption::BestOf(3),
+                            RuleOption::BestOf(5),
+                        ],
+                        &rules,
+                    );
+                    spawn_option_row(
+                        parent,
+                        "Win by",
+                        &[RuleOption::WinBy(1), RuleOption::WinBy(2)],
+                        &rules,
+                    );
+                    spawn_option_row(
+                        parent,
+                        "Deuce cap",
+                        &[
+                            RuleOption::DeuceCap(None),
+                            RuleOption::DeuceCap(Some(2)),
+                            RuleOption::DeuceCap(Some(5)),
+                        ],
+                        &rules,
+                    );
+                    spawn_option_row(
+                        parent,
+                        "Serve after a point",
+                        &[
+                            RuleOption::Serve(ServeRule::Winner),
+                            RuleOption::Serve(ServeRule::Loser),
+                            RuleOption::Serve(ServeRule::Alternate),
+                        ],
+                        &rules,
+                    );
+                    spawn_option_row(
+                        parent,
+                        "Serve angle up to",
+                        &[
+                            RuleOption::ServeCone(0),
+                            RuleOption::ServeCone(15),
+                            RuleOption::ServeCone(30),
+                            RuleOption::ServeCone(45),
+                        ],
+                        &rules,
+                    );
+                    spawn_option_row(
+                        parent,
+                        "Bounce angle up to",
+                        &[
+                            RuleOption::MaxBounceAngle(30),
+                            RuleOption::MaxBounceAngle(45),
+                            RuleOption::MaxBounceAngle(60),
+                            RuleOption::MaxBounceAngle(75),
+                        ],
+                        &rules,
+                    );
+                    spawn_option_row(
+                        parent,
+                        "Spin",
+                        &[RuleOption::Spin(false), RuleOption::Spin(true)],
+                        &rules,
+                    );
+                    spawn_option_row(
+                        parent,
+                        "Lives (4 players)",
+                        &[
+                            RuleOption::Lives(1),
+                            RuleOption::Lives(3),
+                            RuleOption::Lives(5),
+                        ],
+                        &rules,
+                    );
+                    spawn_option_row(
+                        parent,
+                        "Extra balls",
+                        &[
+                            RuleOption::ExtraBalls(ExtraBalls::Off),
+                            RuleOption::ExtraBalls(ExtraBalls::Timed),
+                            RuleOption::ExtraBalls(ExtraBalls::PowerUp),
+                        ],
+                        &rules,
+                    );
+
+                    parent.spawn(NodeBundle::default()).with_children(|parent| {
+                        for (action, text) in actions {
+                            parent
+                                .spawn((
+                                    ButtonBundle {
+                                        style: button_style.clone(),
+                                        background_color: NORMAL_BUTTON.into(),
+                                        ..default()
+                                    },
+                                    action,
+                                ))
+                                .with_children(|parent| {
+                                    parent.spawn(TextBundle::from_section(
+                                        text,
+                                        button_text_style.clone(),
+                                    ));
+                                });
+                        }
+                    });
+                });
+        });
+}
+
+/// Short name of a key for the menus, such as "W" or "ArrowUp"
+pub fn key_name(key: KeyCode) -> String {
+    let name = format!("{:?}", key);
+    match name
+        .strip_prefix("Key")
+        .or_else(|| name.strip_prefix("Digit"))
+    {
+        Some(short) => short.to_string(),
+        None => name,
+    }
+}
+
+fn setup_menu_settings(
+    mut commands: Commands,
+    settings: Res<Settings>,
+    input_map: Res<InputMap>,
+    game_state: Res<State<GameState>>,
+) {
+    let button_style = Style {
+        width: Val::Px(300.0),
+        height: Val::Px(65.0),
+        margin: UiRect::all(Val::Px(20.0)),
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::Center,
+        ..default()
+    };
+
+    let button_text_style = TextStyle {
+        font_size: 40.0,
+        color: TEXT_COLOR,
+        ..default()
+    };
+    let text_style = TextStyle {
+        font_size: 24.0,
+        color: TEXT_COLOR,
+        ..default()
+    };
+
+    // rules can't change in the middle of a match
+    let mut actions = Vec::new();
+    if *game_state.get() == GameState::Menu {
+        actions.push((MenuButtonAction::SettingsMatchRules, "Match Rules"));
+    }
+    actions.push((MenuButtonAction::BackFromSettings, "Back"));
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                ..default()
+            },
+            OnSettingsMenuScreen,
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Column,
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    background_color: Color::ALICE_BLUE.into(),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    for (label, slider) in [
+                        ("Volume", VolumeSlider::Master),
+                        ("Effects volume", VolumeSlider::Effects),
                     ] {
+                        parent
+                            .spawn(NodeBundle {
+                                style: Style {
+                                    align_items: AlignItems::Center,
+                                    ..default()
+                                },
+                                ..default()
+                            })
+                            .with_children(|parent| {
+                                parent.spawn(
+                                    TextBundle::from_section(label, text_style.clone()).with_style(
+                                        Style {
+                                            width: Val::Px(220.0),
+                                            margin: UiRect::all(Val::Px(8.0)),
+                                            ..default()
+                                        },
+                                    ),
+                                );
+                                parent
+                                    .spawn((
+                                        ButtonBundle {
+                                            style: Style {
+                                                width: Val::Px(414.0),
+                                                height: Val::Px(30.0),
+                                                margin: UiRect::all(Val::Px(8.0)),
+                                                ..default()
+                                            },
+                                            background_color: NORMAL_BUTTON.into(),
+                                            ..default()
+                                        },
+                                        slider,
+                                        RelativeCursorPosition::default(),
+                                    ))
+                                    .with_children(|parent| {
+                                        parent.spawn((
+                                            NodeBundle {
+                                                style: Style {
+                                                    width: Val::Percent(
+                                                        slider.value(&settings) * 100.,
+                                                    ),
+                                                    height: Val::Percent(100.0),
+                                                    ..default()
+                                                },
+                                                background_color: PRESSED_BUTTON.into(),
+                                                ..default()
+                                            },
+                                            SliderFill,
+                                        ));
+                                    });
+                            });
+                    }
+
+                    spawn_option_row(
+                        parent,
+                        "FPS counter",
+                        &[SettingOption::ShowFps(true), SettingOption::ShowFps(false)],
+                        &settings,
+                    );
+                    spawn_option_row(
+                        parent,
+                        "Display",
+                        &[
+                            SettingOption::WindowMode(WindowMode::Windowed),
+                            SettingOption::WindowMode(WindowMode::BorderlessFullscreen),
+                            SettingOption::WindowMode(WindowMode::Fullscreen),
+                        ],
+                        &settings,
+                    );
+                    spawn_option_row(
+                        parent,
+                        "Mouse / touch",
+                        &[
+                            SettingOption::PointerControl(None),
+                            SettingOption::PointerControl(Some(Player::A)),
+                            SettingOption::PointerControl(Some(Player::B)),
+                        ],
+                        &settings,
+                    );
+                    spawn_option_row(
+                        parent,
+                        "Mouse speed",
+                        &[0.5, 0.75, 1.]
+                            .map(|share| SettingOption::PointerMaxSpeed(share * PADDLE_SPEED)),
+                        &settings,
+                    );
+
+                    // a row of keys for each player, pressed to bind another key
+                    for player in Player::ALL {
+                        parent.spawn(NodeBundle::default()).with_children(|parent| {
+                            parent.spawn(
+                                TextBundle::from_section(
+                                    format!("Player {:?} keys", player),
+                                    text_style.clone(),
+                                )
+                                .with_style(Style {
+                                    width: Val::Px(220.0),
+                                    margin: UiRect::all(Val::Px(8.0)),
+                                    align_self: AlignSelf::Center,
+                                    ..default()
+                                }),
+                            );
+                            for action in Action::ALL {
+                                parent
+                                    .spawn((
+                                        ButtonBundle {
+                                            style: Style {
+                                                width: Val::Px(130.0),
+                                                height: Val::Px(40.0),
+                                                margin: UiRect::all(Val::Px(4.0)),
+                                                flex_direction: FlexDirection::Column,
+                                                justify_content: JustifyContent::Center,
+                                                align_items: AlignItems::Center,
+                                                ..default()
+                                            },
+                                            background_color: NORMAL_BUTTON.into(),
+                                            ..default()
+                                        },
+                                        RebindButton { player, action },
+                                    ))
+                                    .with_children(|parent| {
+                                        parent.spawn((
+                                            TextBundle::from_section(
+                                                key_name(input_map.key(player, action)),
+                                                text_style.clone(),
+                                            ),
+                                            RebindKey,
+                                        ));
+                                        parent.spawn(TextBundle::from_section(
+                                            action.label_for(player),
+                                            TextStyle {
+                                                font_size: 16.0,
+                                                ..text_style.clone()
+                                            },
+                                        ));
+                                    });
+                            }
+                        });
+                    }
+                    parent.spawn((
+                        TextBundle::from_section("", text_style.clone()).with_style(Style {
+                            margin: UiRect::all(Val::Px(8.0)),
+                            ..default()
+                        }),
+                        RebindStatus,
+                    ));
+
+                    parent.spawn(NodeBundle::default()).with_children(|parent| {
+                        for (action, text) in actions {
+                            parent
+                                .spawn((
+                                    ButtonBundle {
+                                        style: button_style.clone(),
+                                        background_color: NORMAL_BUTTON.into(),
+                                        ..default()
+                                    },
+                                    action,
+                                ))
+                                .with_children(|parent| {
+                                    parent.spawn(TextBundle::from_section(
+                                        text,
+                                        button_text_style.clone(),
+                                    ));
+                                });
+                        }
+                    });
+                });
+        });
+}
+
+// Settings opened from the pause menu are shown over the paused match
+fn open_pause_settings(mut menu_state: ResMut<NextState<MenuState>>) {
+    menu_state.set(MenuState::Settings);
+}
+
+fn close_pause_settings(
+    game_state: Res<State<GameState>>,
+    mut menu_state: ResMut<NextState<MenuState>>,
+) {
+    // leaving the match for the main menu already picked the next menu
+    if *game_state.get() == GameState::Match {
+        menu_state.set(MenuState::Disabled);
+    }
+}
+
+// Any player on this keyboard pauses and resumes with their pause key
+fn toggle_pause(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    input_map: Res<InputMap>,
+    q_keyboard: Query<(&Player, Option<&Seat>), With<KeyboardControl>>,
+    pause_state: Res<State<PauseState>>,
+    mut next_pause_state: ResMut<NextState<PauseState>>,
+) {
+    let pressed = q_keyboard
+        .iter()
+        .map(|(&player, seat)| seat_of(player, seat))
+        .any(|player| keyboard_input.just_pressed(input_map.key(player, Action::Pause)));
+    if pressed {
+        next_pause_state.set(match pause_state.get() {
+            PauseState::Running => PauseState::Paused,
+            PauseState::Paused => PauseState::Running,
+            PauseState::Settings => PauseState::Paused,
+        });
+    }
+}
+
+fn setup_pause_menu(mut commands: Commands, match_mode: Res<MatchMode>) {
+    let button_style = Style {
+        width: Val::Px(300.0),
+        height: Val::Px(65.0),
+        margin: UiRect::all(Val::Px(20.0)),
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::Center,
+        ..default()
+    };
+
+    let button_text_style = TextStyle {
+        font_size: 40.0,
+        color: TEXT_COLOR,
+        ..default()
+    };
+
+    let mut actions = vec![(MenuButtonAction::Resume, "Resume")];
+    // a network match can't be started over by one player alone
+    if !match_mode.is_network() {
+        actions.push((MenuButtonAction::RestartMatch, "Restart Match"));
+    }
+    actions.push((MenuButtonAction::Settings, "Settings"));
+    actions.push((MenuButtonAction::QuitToMenu, "Quit to Menu"));
+
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                background_color: Color::rgba(0., 0., 0., 0.6).into(),
+                ..default()
+            },
+            OnPauseMenuScreen,
+        ))
+        .with_children(|parent| {
+            parent
+                .spawn(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Column,
+                        align_items: AlignItems::Center,
+                        ..default()
+                    },
+                    background_color: Color::ALICE_BLUE.into(),
+                    ..default()
+                })
+                .with_children(|parent| {
+                    parent.spawn(
+                        TextBundle::from_section(
+                            "Paused",
+                            TextStyle {
+                                font_size: 60.0,
+                                color: TEXT_COLOR,
+                                ..default()
+                            },
+                        )
+                        .with_style(Style {
+                            margin: UiRect::all(Val::Px(30.0)),
+                            ..default()
+                        }),
+                    );
+
+                    for (action, text) in actions {
                         parent
                             .spawn((
                                 ButtonBundle {
@@ -281,6 +2891,102 @@ fn setup_menu_multiplayer(mut commands: Commands) {
         });
 }
 
+fn open_lobby(mut commands: Commands) {
+    commands.insert_resource(LanLobby::default());
+}
+
+fn close_lobby(mut commands: Commands) {
+    commands.remove_resource::<LanLobby>();
+}
+
+fn lan_game_label(game: &LanGame) -> String {
+    let ping = match game.ping {
+        Some(ping) => format!("{:.0} ms", ping),
+        None => "? ms".to_string(),
+    };
+    format!("{}  |  {}  |  {}", game.name, ping, game.ruleset)
+}
+
+// Keeps one join button per discovered game
+fn update_lan_games(
+    mut commands: Commands,
+    lobby: Option<Res<LanLobby>>,
+    list_query: Query<Entity, With<LanGameList>>,
+    row_query: Query<(Entity, &MenuButtonAction, &Children)>,
+    mut text_query: Query<&mut Text, With<LanGameText>>,
+) {
+    let Ok(list) = list_query.get_single() else {
+        return;
+    };
+    let games = match &lobby {
+        Some(lobby) => &lobby.games[..],
+        None => &[],
+    };
+
+    let mut listed = Vec::new();
+    for (entity, action, children) in &row_query {
+        let MenuButtonAction::JoinLanGame(addr) = action else {
+            continue;
+        };
+        match games.iter().find(|game| game.addr == *addr) {
+            Some(game) => {
+                listed.push(*addr);
+                let mut texts = text_query.iter_many_mut(children);
+                while let Some(mut text) = texts.fetch_next() {
+                    text.sections[0].value = lan_game_label(game);
+                }
+            }
+            None => commands.entity(entity).despawn_recursive(),
+        }
+    }
+
+    let button_style = Style {
+        width: Val::Px(500.0),
+        height: Val::Px(65.0),
+        margin: UiRect::all(Val::Px(20.0)),
+        justify_content: JustifyContent::Center,
+        align_items: AlignItems::Center,
+        ..default()
+    };
+    let button_text_style = TextStyle {
+        font_size: 24.0,
+        color: TEXT_COLOR,
+        ..default()
+    };
+
+    for game in games.iter().filter(|game| !listed.contains(&game.addr)) {
+        commands.entity(list).with_children(|parent| {
+            parent
+                .spawn((
+                    ButtonBundle {
+                        style: button_style.clone(),
+                        background_color: NORMAL_BUTTON.into(),
+                        ..default()
+                    },
+                    MenuButtonAction::JoinLanGame(game.addr),
+                ))
+                .with_children(|parent| {
+                    parent.spawn((
+                        TextBundle::from_section(lan_game_label(game), button_text_style.clone()),
+                        LanGameText,
+                    ));
+                });
+        });
+    }
+}
+
+fn update_network_status(
+    session: Option<Res<NetSession>>,
+    mut query: Query<&mut Text, With<NetworkStatusText>>,
+) {
+    for mut text in &mut query {
+        text.sections[0].value = match &session {
+            Some(session) => session.status(),
+            None => String::new(),
+        };
+    }
+}
+
 fn menu_action(
     interaction_query: Query<
         (&Interaction, &MenuButtonAction),
@@ -288,7 +2994,19 @@ fn menu_action(
     >,
     mut app_exit_events: EventWriter<AppExit>,
     mut menu_state: ResMut<NextState<MenuState>>,
+    current_game_state: Res<State<GameState>>,
     mut game_state: ResMut<NextState<GameState>>,
+    mut pause_state: ResMut<NextState<PauseState>>,
+    mut match_mode: ResMut<MatchMode>,
+    mut commands: Commands,
+    net_config: Res<NetConfig>,
+    mut profiles: ResMut<Profiles>,
+    mut profile_name_entry: ResMut<ProfileNameEntry>,
+    profiles_file: Res<ProfilesFile>,
+    mut export_status_query: Query<&mut Text, With<ExportStatusText>>,
+    tournament: Option<Res<Tournament>>,
+    mut tournament_entry: ResMut<TournamentEntry>,
+    tournament_file: Res<TournamentFile>,
 ) {
     for (interaction, menu_button_action) in &interaction_query {
         if *interaction == Interaction::Pressed {
@@ -296,20 +3014,161 @@ fn menu_action(
                 MenuButtonAction::Quit => {
                     app_exit_events.send(AppExit);
                 }
-                MenuButtonAction::PlayVsAi => {
-                    game_state.set(GameState::Match);
-                    menu_state.set(MenuState::Disabled);
+                MenuButtonAction::PlayVsAi => menu_state.set(MenuState::VsAi),
+                MenuButtonAction::StartVsAi(difficulty) => {
+                    *match_mode = MatchMode::VsAi(*difficulty);
+                    menu_state.set(MenuState::Join);
                 }
                 MenuButtonAction::Multiplayer => menu_state.set(MenuState::Multiplayer),
                 MenuButtonAction::MultiplayerLocal => {
+                    *match_mode = MatchMode::Local;
+                    menu_state.set(MenuState::Join);
+                }
+                MenuButtonAction::MultiplayerFourPlayer => {
+                    *match_mode = MatchMode::FourPlayer;
+                    menu_state.set(MenuState::Join);
+                }
+                MenuButtonAction::MultiplayerDoubles => {
+                    *match_mode = MatchMode::Doubles([None; 4]);
+                    menu_state.set(MenuState::Join);
+                }
+                MenuButtonAction::MultiplayerNetwork => {
+                    menu_state.set(MenuState::MultiplayerNetwork);
+                }
+                MenuButtonAction::Tournament => match tournament {
+                    Some(_) => menu_state.set(MenuState::Tournament),
+                    None => menu_state.set(MenuState::TournamentEntry),
+                },
+                MenuButtonAction::AddProfilePlayers => {
+                    for profile in &profiles.profiles {
+                        if tournament_entry.names.len() < MAX_PLAYERS
+                            && !tournament_entry.names.contains(&profile.name)
+                        {
+                            tournament_entry.names.push(profile.name.clone());
+                        }
+                    }
+                }
+                MenuButtonAction::StartTournament => {
+                    let names = tournament_entry.names.clone();
+                    match Tournament::new(names, tournament_entry.format) {
+                        Some(tournament) => {
+                            tournament_file.save(&tournament);
+                            commands.insert_resource(tournament);
+                            *tournament_entry = TournamentEntry::default();
+                            menu_state.set(MenuState::Tournament);
+                        }
+                        None => {
+                            tournament_entry.error = Some(format!(
+                                "a tournament needs {} to {} players",
+                                MIN_PLAYERS, MAX_PLAYERS
+                            ));
+                        }
+                    }
+                }
+                MenuButtonAction::PlayTournamentMatch => {
+                    let Some(tournament) = &tournament else {
+                        continue;
+                    };
+                    let Some(index) = tournament.next_match() else {
+                        continue;
+                    };
+                    if let Some((a, b)) = tournament.opponents(index) {
+                        // players with a profile of their name play as it
+                        profiles.select(Player::A, a);
+                        profiles.select(Player::B, b);
+                    }
+                    commands.insert_resource(TournamentMatch(index));
+                    *match_mode = MatchMode::Local;
                     menu_state.set(MenuState::Disabled);
                     game_state.set(GameState::Match);
                 }
-                MenuButtonAction::MultiplayerNetwork => {
-                    // menu_state.set(MenuState::Disabled);
-                    // game_state.set(GameState::NetworkMatch);
+                MenuButtonAction::EndTournament => {
+                    commands.remove_resource::<Tournament>();
+                    tournament_file.clear();
+                    menu_state.set(MenuState::TournamentEntry);
+                }
+                // the match starts from the net plugin once the other player is connected
+                MenuButtonAction::HostNetwork => match NetSession::host(net_config.port) {
+                    Ok(session) => {
+                        commands.insert_resource(session);
+                        // free the discovery port for players on this machine who want to join
+                        commands.remove_resource::<LanLobby>();
+                    }
+                    Err(err) => warn!("could not host on port {}: {}", net_config.port, err),
+                },
+                MenuButtonAction::JoinNetwork => match NetSession::join(net_config.join_addr) {
+                    Ok(session) => commands.insert_resource(session),
+                    Err(err) => warn!("could not join {}: {}", net_config.join_addr, err),
+                },
+                MenuButtonAction::JoinLanGame(addr) => match NetSession::join(*addr) {
+                    Ok(session) => commands.insert_resource(session),
+                    Err(err) => warn!("could not join {}: {}", addr, err),
+                },
+                MenuButtonAction::Replays => menu_state.set(MenuState::Replays),
+                MenuButtonAction::Stats => menu_state.set(MenuState::Stats),
+                MenuButtonAction::NewProfile => {
+                    *profile_name_entry = ProfileNameEntry {
+                        name: Some(String::new()),
+                        error: None,
+                    };
+                }
+                MenuButtonAction::PickProfile(player) => profiles.cycle(*player),
+                MenuButtonAction::SwitchSeatAi(player) => {
+                    if let MatchMode::Doubles(seats) = &mut *match_mode {
+                        seats[player.index()] = next_seat_ai(seats[player.index()]);
+                    }
+                }
+                MenuButtonAction::Leaderboard => menu_state.set(MenuState::Leaderboard),
+                MenuButtonAction::ExportHistory => {
+                    let status = match profiles_file.export_history(&profiles) {
+                        Ok(dir) => format!("Exported to {}", dir.display()),
+                        Err(err) => {
+                            warn!("can't export the match history: {}", err);
+                            "Export failed".to_string()
+                        }
+                    };
+                    for mut text in &mut export_status_query {
+                        text.sections[0].value = status.clone();
+                    }
+                }
+                MenuButtonAction::PlayReplay(path) => {
+                    if let Some(replay) = load_replay(path) {
+                        commands.insert_resource(ReplayPlayer::new(replay));
+                        *match_mode = MatchMode::Replay;
+                        menu_state.set(MenuState::Disabled);
+                        game_state.set(GameState::Match);
+                    }
+                }
+                MenuButtonAction::PickMatchRules => menu_state.set(MenuState::MatchRules),
+                MenuButtonAction::StartMatch => {
+                    menu_state.set(MenuState::Disabled);
+                    game_state.set(GameState::Match);
+                }
+                MenuButtonAction::Settings => {
+                    if *current_game_state.get() == GameState::Match {
+                        pause_state.set(PauseState::Settings);
+                    } else {
+                        menu_state.set(MenuState::Settings);
+                    }
+                }
+                MenuButtonAction::SettingsMatchRules => {
+                    menu_state.set(MenuState::SettingsMatchRules);
+                }
+                MenuButtonAction::BackFromSettings => {
+                    if *current_game_state.get() == GameState::Match {
+                        pause_state.set(PauseState::Paused);
+                    } else {
+                        menu_state.set(MenuState::Main);
+                    }
+                }
+                MenuButtonAction::Resume => pause_state.set(PauseState::Running),
+                // leaving the match clears it and unpauses, then it is entered again
+                MenuButtonAction::RestartMatch => game_state.set(GameState::Restarting),
+                MenuButtonAction::QuitToMenu => game_state.set(GameState::Menu),
+                MenuButtonAction::BackToMenu => {
+                    commands.remove_resource::<NetSession>();
+                    menu_state.set(MenuState::Main);
                 }
-                MenuButtonAction::BackToMenu => menu_state.set(MenuState::Main),
             }
         }
     }
@@ -0,0 +1,238 @@
+use bevy::{
+    math::bounding::{Aabb2d, BoundingCircle, IntersectsVolume},
+    prelude::*,
+};
+
+use crate::{
+    match_::{
+        move_ball, spawn_ball, Ball, InGoal, OnMatchView, RoundState, RoundTick, Simulation,
+        Velocity,
+    },
+    rules::{ExtraBalls, MatchRules},
+    CollisionEvent, MatchInfo, MatchMode, RoundData, BALL_RADIUS, BALL_START_POSITION,
+};
+
+/// Ticks between two extra balls with the timed rule, five seconds at the default timestep
+const EXTRA_BALL_TICKS: u32 = 320;
+
+/// Ticks between two power-ups, three seconds at the default timestep
+const POWER_UP_TICKS: u32 = 192;
+
+/// Most balls in play at once
+const MAX_BALLS: usize = 5;
+
+/// Degrees each half of a split ball turns off the path of the ball
+const SPLIT_ANGLE: f32 = 20.;
+
+const POWER_UP_SIZE: Vec3 = Vec3::new(30., 30., 0.);
+const POWER_UP_COLOR: Color = Color::rgb(1., 0.8, 0.);
+
+/// Where power-ups show up in turn, away from the paddles and the centre spot
+const POWER_UP_SPOTS: [Vec2; 4] = [
+    Vec2::new(-150., 150.),
+    Vec2::new(150., -150.),
+    Vec2::new(150., 150.),
+    Vec2::new(-150., -150.),
+];
+
+pub fn multi_ball_plugin(app: &mut App) {
+    app.add_systems(
+        Simulation,
+        (spawn_timed_ball, spawn_power_up, split_balls, collide_balls)
+            .chain()
+            .after(move_ball)
+            .run_if(extra_balls_on),
+    )
+    .add_systems(OnEnter(RoundState::Countdown), despawn_power_ups);
+}
+
+/// Splits the ball that runs into it in two
+#[derive(Component)]
+struct PowerUp;
+
+/// Whether the rules add balls to this match. Network matches only roll back one ball.
+fn extra_balls_on(rules: Res<MatchRules>, match_mode: Res<MatchMode>) -> bool {
+    rules.extra_balls != ExtraBalls::Off && !match_mode.is_network()
+}
+
+// Extra balls start from the centre, served to each side in turn
+fn spawn_timed_ball(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    ball_query: Query<(), With<Ball>>,
+    rules: Res<MatchRules>,
+    tick: Res<RoundTick>,
+    round_data: Res<RoundData>,
+    match_: Res<MatchInfo>,
+) {
+    if rules.extra_balls != ExtraBalls::Timed
+        || tick.0 == 0
+        || !tick.0.is_multiple_of(EXTRA_BALL_TICKS)
+        || round_data.balls_in_play == 0
+        || ball_query.iter().count() >= MAX_BALLS
+    {
+        return;
+    }
+    let direction = if (tick.0 / EXTRA_BALL_TICKS).is_multiple_of(2) {
+        1.
+    } else {
+        -1.
+    };
+    spawn_ball(
+        &mut commands,
+        &mut meshes,
+        &mut materials,
+        BALL_START_POSITION,
+        match_.serve * direction,
+    );
+}
+
+fn spawn_power_up(
+    mut commands: Commands,
+    power_up_query: Query<(), With<PowerUp>>,
+    rules: Res<MatchRules>,
+    tick: Res<RoundTick>,
+) {
+    if rules.extra_balls != ExtraBalls::PowerUp
+        || tick.0 == 0
+        || !tick.0.is_multiple_of(POWER_UP_TICKS)
+        || !power_up_query.is_empty()
+    {
+        return;
+    }
+    let spot = POWER_UP_SPOTS[(tick.0 / POWER_UP_TICKS) as usize % POWER_UP_SPOTS.len()];
+    commands.spawn((
+        SpriteBundle {
+            transform: Transform {
+                translation: spot.extend(0.),
+                scale: POWER_UP_SIZE,
+                ..default()
+            },
+            sprite: Sprite {
+                color: POWER_UP_COLOR,
+                ..default()
+            },
+            ..default()
+        },
+        PowerUp,
+        OnMatchView,
+    ));
+}
+
+// The ball turns one way and a new one leaves the other way at the same speed
+fn split_balls(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+    mut ball_query: Query<(&Transform, &mut Velocity), With<Ball>>,
+    power_up_query: Query<(Entity, &Transform), (With<PowerUp>, Without<Ball>)>,
+    round_data: Res<RoundData>,
+) {
+    if round_data.balls_in_play == 0 {
+        return;
+    }
+    let mut balls = ball_query.iter().count();
+    for (power_up, power_up_transform) in &power_up_query {
+        let boundary = Aabb2d::new(
+            power_up_transform.translation.truncate(),
+            power_up_transform.scale.truncate() / 2.,
+        );
+        let hit = ball_query.iter_mut().find(|(transform, _)| {
+            BoundingCircle::new(transform.translation.truncate(), BALL_RADIUS).intersects(&boundary)
+        });
+        let Some((transform, mut velocity)) = hit else {
+            continue;
+        };
+        commands.entity(power_up).despawn();
+        if balls >= MAX_BALLS {
+            continue;
+        }
+
+        let turn = Vec2::from_angle(SPLIT_ANGLE.to_radians());
+        let split = Vec2::from_angle(-SPLIT_ANGLE.to_radians()).rotate(velocity.0);
+        velocity.0 = turn.rotate(velocity.0);
+        spawn_ball(
+            &mut commands,
+            &mut meshes,
+            &mut materials,
+            transform.translation,
+            split,
+        );
+        balls += 1;
+    }
+}
+
+/// Balls bouncing off each other as if of the same mass: the parts of their velocities along
+/// the line between their centres swap
+fn collide_balls(
+    mut ball_query: Query<(&Transform, &mut Velocity), (With<Ball>, Without<InGoal>)>,
+    mut collision_events: EventWriter<CollisionEvent>,
+) {
+    let mut pairs = ball_query.iter_combinations_mut();
+    while let Some([(a, mut velocity_a), (b, mut velocity_b)]) = pairs.fetch_next() {
+        let offset = b.translation.truncate() - a.translation.truncate();
+        if offset.length() > 2. * BALL_RADIUS {
+            continue;
+        }
+        let Some(normal) = offset.try_normalize() else {
+            continue;
+        };
+        // already moving apart
+        let closing = (velocity_a.0 - velocity_b.0).dot(normal);
+        if closing <= 0. {
+            continue;
+        }
+        velocity_a.0 -= closing * normal;
+        velocity_b.0 += closing * normal;
+        collision_events.send(CollisionEvent::Wall);
+    }
+}
+
+fn despawn_power_ups(mut commands: Commands, query: Query<Entity, With<PowerUp>>) {
+    for entity in &query {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn balls_meeting_head_on_swap_velocities() {
+        let mut app = App::new();
+        app.add_event::<CollisionEvent>()
+            .add_systems(Update, collide_balls);
+        let a = app
+            .world
+            .spawn((
+                Transform::from_xyz(-9., 0., 1.),
+                Ball,
+                Velocity(Vec2::new(800., 100.)),
+            ))
+            .id();
+        let b = app
+            .world
+            .spawn((
+                Transform::from_xyz(9., 0., 1.),
+                Ball,
+                Velocity(Vec2::new(-400., 0.)),
+            ))
+            .id();
+
+        app.update();
+        assert_eq!(
+            app.world.get::<Velocity>(a).unwrap().0,
+            Vec2::new(-400., 100.)
+        );
+        assert_eq!(app.world.get::<Velocity>(b).unwrap().0, Vec2::new(800., 0.));
+
+        // moving apart now, they don't bounce back into each other
+        app.update();
+        assert_eq!(
+            app.world.get::<Velocity>(a).unwrap().0,
+            Vec2::new(-400., 100.)
+        );
+    }
+}
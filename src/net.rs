@@ -0,0 +1,737 @@
+use std::{
+    io::ErrorKind,
+    net::{SocketAddr, UdpSocket},
+};
+
+use bevy::prelude::*;
+
+use crate::{
+    match_::{Player, RoundState},
+    rollback::{RemoteInputs, Rollback},
+    rules::{ExtraBalls, MatchRules, ServeRule},
+    GameState, MatchInfo, MatchMode, ScoreEvent, Scores,
+};
+
+pub const DEFAULT_PORT: u16 = 7878;
+
+// first bytes of every packet, anything else on the port is ignored
+const PROTOCOL_ID: [u8; 4] = *b"PNG4";
+
+/// Seconds without hearing from the peer before giving up on the match
+const PEER_TIMEOUT: f32 = 5.;
+const HELLO_INTERVAL: f32 = 0.5;
+
+pub fn net_plugin(app: &mut App) {
+    app.insert_resource(NetConfig::from_args())
+        .add_event::<HostStatus>()
+        .add_systems(
+            PreUpdate,
+            (receive_messages, apply_host_status)
+                .chain()
+                .run_if(resource_exists::<NetSession>),
+        )
+        .add_systems(
+            Update,
+            start_network_match
+                .run_if(in_state(GameState::Menu).and_then(resource_exists::<NetSession>)),
+        )
+        .add_systems(
+            PostUpdate,
+            send_messages.run_if(resource_exists::<NetSession>),
+        )
+        .add_systems(OnEnter(GameState::Menu), close_session);
+}
+
+/// Addresses used by the Host and Join buttons and the name hosted games are listed under,
+/// overridable with `--port <port>`, `--join <address:port>` and `--name <name>`
+#[derive(Resource)]
+pub struct NetConfig {
+    pub port: u16,
+    pub join_addr: SocketAddr,
+    pub name: String,
+}
+
+impl NetConfig {
+    fn from_args() -> NetConfig {
+        let mut config = NetConfig {
+            port: DEFAULT_PORT,
+            join_addr: SocketAddr::from(([127, 0, 0, 1], DEFAULT_PORT)),
+            name: ["HOSTNAME", "COMPUTERNAME", "USER", "USERNAME"]
+                .into_iter()
+                .find_map(|var| std::env::var(var).ok())
+                .unwrap_or_else(|| "pong".to_string()),
+        };
+
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match (arg.as_str(), args.next()) {
+                ("--port", Some(port)) => match port.parse() {
+                    Ok(port) => config.port = port,
+                    Err(_) => warn!("ignoring invalid port {}", port),
+                },
+                ("--join", Some(addr)) => match addr.parse() {
+                    Ok(addr) => config.join_addr = addr,
+                    Err(_) => warn!("ignoring invalid join address {}", addr),
+                },
+                ("--name", Some(name)) => config.name = name,
+                _ => {}
+            }
+        }
+
+        config
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum NetRole {
+    Host,
+    Client,
+}
+
+/// Marks the paddle steered by the other machine
+#[derive(Component)]
+pub struct RemoteControl;
+
+/// An open connection, or an attempt at one while in the menu
+#[derive(Resource)]
+pub struct NetSession {
+    socket: UdpSocket,
+    pub role: NetRole,
+    peer: Option<SocketAddr>,
+    pub connected: bool,
+    // seconds since startup when the peer was last heard from
+    last_heard: f32,
+    hello_timer: Timer,
+    // host: sequence of the last status sent, client: sequence of the last one applied
+    sequence: u32,
+    // client: round state of the last status, to follow the host's transitions
+    host_round: RoundState,
+    // host: last player to score, sent along so the client can announce it
+    last_scorer: Option<Player>,
+}
+
+impl NetSession {
+    pub fn host(port: u16) -> std::io::Result<NetSession> {
+        let socket = UdpSocket::bind(("0.0.0.0", port))?;
+        info!("hosting on port {}", port);
+        NetSession::new(socket, NetRole::Host, None)
+    }
+
+    pub fn join(addr: SocketAddr) -> std::io::Result<NetSession> {
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        info!("joining {}", addr);
+        let session = NetSession::new(socket, NetRole::Client, Some(addr))?;
+        session.send(&Message::Hello);
+        Ok(session)
+    }
+
+    fn new(
+        socket: UdpSocket,
+        role: NetRole,
+        peer: Option<SocketAddr>,
+    ) -> std::io::Result<NetSession> {
+        socket.set_nonblocking(true)?;
+        Ok(NetSession {
+            socket,
+            role,
+            peer,
+            connected: false,
+            last_heard: 0.,
+            hello_timer: Timer::from_seconds(HELLO_INTERVAL, TimerMode::Repeating),
+            sequence: 0,
+            host_round: RoundState::Out,
+            last_scorer: None,
+        })
+    }
+
+    /// Local port of the game socket
+    pub fn port(&self) -> Option<u16> {
+        self.socket.local_addr().ok().map(|addr| addr.port())
+    }
+
+    /// Text for the lobby screen
+    pub fn status(&self) -> String {
+        match (self.role, self.connected) {
+            (_, true) => "Connected!".to_string(),
+            (NetRole::Host, false) => match self.port() {
+                Some(port) => format!("Waiting for a player on port {}...", port),
+                None => "Waiting for a player...".to_string(),
+            },
+            (NetRole::Client, false) => match self.peer {
+                Some(addr) => format!("Connecting to {}...", addr),
+                None => "Connecting...".to_string(),
+            },
+        }
+    }
+
+    fn send(&self, message: &Message) {
+        let Some(peer) = self.peer else {
+            return;
+        };
+        if let Err(err) = self.socket.send_to(&message.encode(), peer) {
+            warn!("failed to send to {}: {}", peer, err);
+        }
+    }
+}
+
+/// Scores, games and round progress sent by the host to the client
+#[derive(Event)]
+pub struct HostStatus(pub MatchStatus);
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct MatchStatus {
+    pub sequence: u32,
+    pub score_a: u16,
+    pub score_b: u16,
+    pub games_a: u16,
+    pub games_b: u16,
+    pub scorer: Option<Player>,
+    /// Player serving the next round
+    pub server: Player,
+    /// Velocity of the next serve
+    pub serve: Vec2,
+    pub round: RoundState,
+    pub ended: bool,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Message {
+    Hello,
+    /// Accepts the client into a match played by the host's rules
+    Welcome(MatchRules),
+    /// Paddle inputs of the sender's round `round` from `first_tick` on, and how many of
+    /// the receiver's inputs for that round the sender already has
+    Inputs {
+        round: u16,
+        ack: u32,
+        first_tick: u32,
+        directions: Vec<i8>,
+    },
+    Status(MatchStatus),
+    Bye,
+    /// Broadcast by hosts waiting for a player, `port` being the game port
+    Announce {
+        name: String,
+        port: u16,
+        ruleset: String,
+    },
+    Ping(u32),
+    Pong(u32),
+}
+
+impl Message {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = PROTOCOL_ID.to_vec();
+        match self {
+            Message::Hello => bytes.push(0),
+            Message::Welcome(rules) => {
+                bytes.push(1);
+                push_rules(&mut bytes, rules);
+            }
+            Message::Inputs {
+                round,
+                ack,
+                first_tick,
+                directions,
+            } => {
+                bytes.push(2);
+                bytes.extend(round.to_le_bytes());
+                bytes.extend(ack.to_le_bytes());
+                bytes.extend(first_tick.to_le_bytes());
+                bytes.push(directions.len() as u8);
+                bytes.extend(directions.iter().map(|&direction| direction as u8));
+            }
+            Message::Status(status) => {
+                bytes.push(3);
+                bytes.extend(status.sequence.to_le_bytes());
+                bytes.extend(status.score_a.to_le_bytes());
+                bytes.extend(status.score_b.to_le_bytes());
+                bytes.extend(status.games_a.to_le_bytes());
+                bytes.extend(status.games_b.to_le_bytes());
+                bytes.push(match status.scorer {
+                    None => 0,
+                    Some(Player::A) => 1,
+                    Some(Player::B) => 2,
+                    Some(Player::C) => 3,
+                    Some(Player::D) => 4,
+                });
+                bytes.push(match status.server {
+                    Player::A => 0,
+                    Player::B => 1,
+                    Player::C => 2,
+                    Player::D => 3,
+                });
+                bytes.extend(status.serve.x.to_le_bytes());
+                bytes.extend(status.serve.y.to_le_bytes());
+                bytes.push(match status.round {
+                    RoundState::Out => 0,
+                    RoundState::In => 1,
+                    RoundState::Scored => 2,
+                    RoundState::Countdown => 3,
+                });
+                bytes.push(status.ended as u8);
+            }
+            Message::Bye => bytes.push(4),
+            Message::Announce {
+                name,
+                port,
+                ruleset,
+            } => {
+                bytes.push(5);
+                push_str(&mut bytes, name);
+                bytes.extend(port.to_le_bytes());
+                push_str(&mut bytes, ruleset);
+            }
+            Message::Ping(nonce) => {
+                bytes.push(6);
+                bytes.extend(nonce.to_le_bytes());
+            }
+            Message::Pong(nonce) => {
+                bytes.push(7);
+                bytes.extend(nonce.to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<Message> {
+        let mut reader = Reader(bytes.strip_prefix(&PROTOCOL_ID)?);
+        let message = match reader.u8()? {
+            0 => Message::Hello,
+            1 => Message::Welcome(read_rules(&mut reader)?),
+            2 => {
+                let round = reader.u16()?;
+                let ack = reader.u32()?;
+                let first_tick = reader.u32()?;
+                let count = reader.u8()? as usize;
+                let directions = (0..count)
+                    .map(|_| reader.u8().map(|direction| direction as i8))
+                    .collect::<Option<Vec<i8>>>()?;
+                Message::Inputs {
+                    round,
+                    ack,
+                    first_tick,
+                    directions,
+                }
+            }
+            3 => Message::Status(MatchStatus {
+                sequence: reader.u32()?,
+                score_a: reader.u16()?,
+                score_b: reader.u16()?,
+                games_a: reader.u16()?,
+                games_b: reader.u16()?,
+                scorer: match reader.u8()? {
+                    0 => None,
+                    1 => Some(Player::A),
+                    2 => Some(Player::B),
+                    3 => Some(Player::C),
+                    4 => Some(Player::D),
+                    _ => return None,
+                },
+                server: match reader.u8()? {
+                    0 => Player::A,
+                    1 => Player::B,
+                    2 => Player::C,
+                    3 => Player::D,
+                    _ => return None,
+                },
+                serve: Vec2::new(reader.f32()?, reader.f32()?),
+                round: match reader.u8()? {
+                    0 => RoundState::Out,
+                    1 => RoundState::In,
+                    2 => RoundState::Scored,
+                    3 => RoundState::Countdown,
+                    _ => return None,
+                },
+                ended: reader.u8()? != 0,
+            }),
+            4 => Message::Bye,
+            5 => Message::Announce {
+                name: reader.string()?,
+                port: reader.u16()?,
+                ruleset: reader.string()?,
+            },
+            6 => Message::Ping(reader.u32()?),
+            7 => Message::Pong(reader.u32()?),
+            _ => return None,
+        };
+        Some(message)
+    }
+}
+
+/// Strings are prefixed with their length in a byte, longer ones get cut short
+fn push_str(bytes: &mut Vec<u8>, value: &str) {
+    let mut len = value.len().min(u8::MAX as usize);
+    while !value.is_char_boundary(len) {
+        len -= 1;
+    }
+    bytes.push(len as u8);
+    bytes.extend(&value.as_bytes()[..len]);
+}
+
+/// Match rules as sent to network clients and saved in replays
+pub fn push_rules(bytes: &mut Vec<u8>, rules: &MatchRules) {
+    bytes.extend((rules.points_to_win as u16).to_le_bytes());
+    bytes.extend((rules.best_of as u16).to_le_bytes());
+    bytes.extend((rules.win_by as u16).to_le_bytes());
+    let deuce_cap = rules.deuce_cap.map_or(u16::MAX, |cap| cap as u16);
+    bytes.extend(deuce_cap.to_le_bytes());
+    bytes.push(match rules.serve {
+        ServeRule::Winner => 0,
+        ServeRule::Loser => 1,
+        ServeRule::Alternate => 2,
+    });
+    bytes.extend((rules.serve_cone as u16).to_le_bytes());
+    bytes.extend((rules.max_bounce_angle as u16).to_le_bytes());
+    bytes.push(rules.spin as u8);
+}
+
+pub fn read_rules(reader: &mut Reader) -> Option<MatchRules> {
+    Some(MatchRules {
+        points_to_win: reader.u16()? as usize,
+        best_of: reader.u16()? as usize,
+        win_by: reader.u16()? as usize,
+        deuce_cap: match reader.u16()? {
+            u16::MAX => None,
+            cap => Some(cap as usize),
+        },
+        serve: match reader.u8()? {
+            0 => ServeRule::Winner,
+            1 => ServeRule::Loser,
+            2 => ServeRule::Alternate,
+            _ => return None,
+        },
+        serve_cone: reader.u16()? as usize,
+        max_bounce_angle: reader.u16()? as usize,
+        spin: reader.u8()? != 0,
+        // four-player matches aren't played over the network
+        lives: MatchRules::default().lives,
+        // rollback only keeps one ball
+        extra_balls: ExtraBalls::Off,
+    })
+}
+
+/// Reads little-endian values off the front of a byte slice, None once it runs out
+pub struct Reader<'a>(pub &'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        if self.0.len() < N {
+            return None;
+        }
+        let (head, rest) = self.0.split_at(N);
+        self.0 = rest;
+        head.try_into().ok()
+    }
+
+    pub fn u8(&mut self) -> Option<u8> {
+        self.take::<1>().map(|b| b[0])
+    }
+
+    pub fn u16(&mut self) -> Option<u16> {
+        self.take().map(u16::from_le_bytes)
+    }
+
+    pub fn u32(&mut self) -> Option<u32> {
+        self.take().map(u32::from_le_bytes)
+    }
+
+    pub fn u64(&mut self) -> Option<u64> {
+        self.take().map(u64::from_le_bytes)
+    }
+
+    pub fn f32(&mut self) -> Option<f32> {
+        self.take().map(f32::from_le_bytes)
+    }
+
+    pub fn string(&mut self) -> Option<String> {
+        let len = self.u8()? as usize;
+        if self.0.len() < len {
+            return None;
+        }
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        String::from_utf8(head.to_vec()).ok()
+    }
+}
+
+fn receive_messages(
+    mut commands: Commands,
+    mut session: ResMut<NetSession>,
+    rules: Res<MatchRules>,
+    time: Res<Time<Real>>,
+    game_state: Res<State<GameState>>,
+    mut next_game_state: ResMut<NextState<GameState>>,
+    mut inputs: EventWriter<RemoteInputs>,
+    mut statuses: EventWriter<HostStatus>,
+) {
+    let now = time.elapsed_seconds();
+    let mut buf = [0; 1024];
+    let mut peer_left = false;
+
+    loop {
+        let (len, from) = match session.socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+            Err(err) => {
+                // e.g. connection refused on loopback while the host isn't up yet
+                debug!("receive failed: {}", err);
+                break;
+            }
+        };
+        let Some(message) = Message::decode(&buf[..len]) else {
+            continue;
+        };
+
+        // lobby screens measuring their ping to this game
+        if let (NetRole::Host, Message::Ping(nonce)) = (session.role, &message) {
+            session
+                .socket
+                .send_to(&Message::Pong(*nonce).encode(), from)
+                .ok();
+            continue;
+        }
+
+        if let (NetRole::Host, Message::Hello, None) = (session.role, &message, session.peer) {
+            info!("player joined from {}", from);
+            session.peer = Some(from);
+            session.connected = true;
+        }
+        if session.peer != Some(from) {
+            continue;
+        }
+
+        match (session.role, message) {
+            (NetRole::Host, Message::Hello) => session.send(&Message::Welcome(*rules)),
+            (NetRole::Client, Message::Welcome(host_rules)) if !session.connected => {
+                info!("connected to {}, playing {}", from, host_rules.describe());
+                commands.insert_resource(host_rules);
+                session.connected = true;
+            }
+            (NetRole::Client, Message::Status(status)) => {
+                statuses.send(HostStatus(status));
+            }
+            (
+                _,
+                Message::Inputs {
+                    round,
+                    ack,
+                    first_tick,
+                    directions,
+                },
+            ) => {
+                inputs.send(RemoteInputs {
+                    round,
+                    ack,
+                    first_tick,
+                    directions,
+                });
+            }
+            (_, Message::Bye) => peer_left = true,
+            _ => {}
+        }
+        session.last_heard = now;
+    }
+
+    if session.connected && now - session.last_heard > PEER_TIMEOUT {
+        info!("lost connection to peer");
+        peer_left = true;
+    }
+
+    if peer_left {
+        info!("peer left, closing session");
+        commands.remove_resource::<NetSession>();
+        if *game_state.get() == GameState::Match {
+            next_game_state.set(GameState::Menu);
+        }
+    }
+}
+
+fn apply_host_status(
+    mut session: ResMut<NetSession>,
+    mut statuses: EventReader<HostStatus>,
+    mut scores: ResMut<Scores>,
+    mut match_: ResMut<MatchInfo>,
+    game_state: Res<State<GameState>>,
+    mut next_game_state: ResMut<NextState<GameState>>,
+    mut next_round_state: ResMut<NextState<RoundState>>,
+    mut score_events: EventWriter<ScoreEvent>,
+) {
+    for HostStatus(status) in statuses.read() {
+        // packets can arrive out of order, skip anything older than what's applied
+        if status.sequence <= session.sequence {
+            continue;
+        }
+        session.sequence = status.sequence;
+
+        scores.a = status.score_a as usize;
+        scores.b = status.score_b as usize;
+        match_.games_a = status.games_a as usize;
+        match_.games_b = status.games_b as usize;
+        match_.server = status.server;
+        match_.serve = status.serve;
+
+        if status.ended {
+            if *game_state.get() == GameState::Match {
+                next_game_state.set(GameState::End);
+            }
+            continue;
+        }
+
+        // follow the host's round transitions as they happen
+        if status.round != session.host_round {
+            session.host_round = status.round;
+            if status.round == RoundState::Scored {
+                match status.scorer {
+                    Some(Player::A) => {
+                        score_events.send(ScoreEvent::A);
+                    }
+                    Some(Player::B) => {
+                        score_events.send(ScoreEvent::B);
+                    }
+                    // four-player matches aren't played over the network
+                    Some(Player::C | Player::D) | None => {}
+                }
+            }
+            next_round_state.set(status.round);
+        }
+    }
+}
+
+fn start_network_match(
+    session: Res<NetSession>,
+    mut match_mode: ResMut<MatchMode>,
+    mut game_state: ResMut<NextState<GameState>>,
+) {
+    if session.connected {
+        *match_mode = match session.role {
+            NetRole::Host => MatchMode::NetworkHost,
+            NetRole::Client => MatchMode::NetworkClient,
+        };
+        game_state.set(GameState::Match);
+    }
+}
+
+fn send_messages(
+    mut session: ResMut<NetSession>,
+    time: Res<Time<Real>>,
+    game_state: Res<State<GameState>>,
+    round_state: Res<State<RoundState>>,
+    scores: Res<Scores>,
+    match_: Res<MatchInfo>,
+    rollback: Option<Res<Rollback>>,
+    mut score_events: EventReader<ScoreEvent>,
+) {
+    if let Some(event) = score_events.read().last() {
+        session.last_scorer = Some(match event {
+            ScoreEvent::A => Player::A,
+            ScoreEvent::B => Player::B,
+        });
+    }
+
+    if !session.connected {
+        if session.role == NetRole::Client && session.hello_timer.tick(time.delta()).just_finished()
+        {
+            session.send(&Message::Hello);
+        }
+        return;
+    }
+
+    if let Some(rollback) = rollback {
+        let (first_tick, directions) = rollback.unacked_inputs();
+        session.send(&Message::Inputs {
+            round: rollback.round,
+            ack: rollback.remote_confirmed,
+            first_tick,
+            directions: directions.to_vec(),
+        });
+    }
+
+    if session.role == NetRole::Host && *game_state.get() != GameState::Menu {
+        session.sequence += 1;
+        session.send(&Message::Status(MatchStatus {
+            sequence: session.sequence,
+            score_a: scores.a as u16,
+            score_b: scores.b as u16,
+            games_a: match_.games_a as u16,
+            games_b: match_.games_b as u16,
+            scorer: session.last_scorer,
+            server: match_.server,
+            serve: match_.serve,
+            round: *round_state.get(),
+            ended: *game_state.get() == GameState::End,
+        }));
+    }
+}
+
+/// Drops the connection when going back to the menu, letting the peer know
+pub fn close_session(mut commands: Commands, session: Option<Res<NetSession>>) {
+    if let Some(session) = session {
+        session.send(&Message::Bye);
+        commands.remove_resource::<NetSession>();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_survive_encoding() {
+        let messages = [
+            Message::Hello,
+            Message::Welcome(MatchRules {
+                points_to_win: 11,
+                best_of: 3,
+                deuce_cap: Some(2),
+                serve: ServeRule::Alternate,
+                spin: true,
+                ..default()
+            }),
+            Message::Inputs {
+                round: 3,
+                ack: 70_000,
+                first_tick: 12,
+                directions: vec![127, -127, 0, 64],
+            },
+            Message::Status(MatchStatus {
+                sequence: 42,
+                score_a: 5,
+                score_b: 7,
+                games_a: 1,
+                games_b: 0,
+                scorer: Some(Player::B),
+                server: Player::A,
+                serve: Vec2::new(-800., 12.5),
+                round: RoundState::Scored,
+                ended: false,
+            }),
+            Message::Bye,
+            Message::Announce {
+                name: "pong-host".to_string(),
+                port: DEFAULT_PORT,
+                ruleset: MatchRules::default().describe(),
+            },
+            Message::Ping(9),
+            Message::Pong(9),
+        ];
+
+        for message in messages {
+            assert_eq!(Message::decode(&message.encode()), Some(message));
+        }
+    }
+
+    #[test]
+    fn foreign_and_cut_short_packets_are_ignored() {
+        let inputs = Message::Inputs {
+            round: 1,
+            ack: 0,
+            first_tick: 0,
+            directions: vec![127],
+        }
+        .encode();
+
+        assert_eq!(Message::decode(b"GET / HTTP/1.1"), None);
+        assert_eq!(Message::decode(&inputs[..inputs.len() - 1]), None);
+    }
+}
@@ -0,0 +1,125 @@
+use bevy::{prelude::*, window::PrimaryWindow};
+
+use crate::{
+    doubles::{seat_of, Seat},
+    gamepad::read_gamepad_input,
+    match_::{
+        read_keyboard_input, KeyboardControl, MatchSet, Paddle, PaddleInput, PauseState, Player,
+        PADDLE_BOTTOM_BOUND, PADDLE_TOP_BOUND,
+    },
+    settings::Settings,
+    PADDLE_SPEED,
+};
+
+pub fn pointer_plugin(app: &mut App) {
+    app.add_systems(
+        Update,
+        read_pointer_input
+            .after(read_keyboard_input)
+            .after(read_gamepad_input)
+            .run_if(in_state(PauseState::Running))
+            .in_set(MatchSet),
+    );
+}
+
+/// Direction that moves a paddle at `paddle_y` towards the pointer at `target_y` within one
+/// tick of `timestep` seconds, no faster than `max_speed` and not past the walls
+pub fn pointer_direction(paddle_y: f32, target_y: f32, max_speed: f32, timestep: f32) -> f32 {
+    let distance = target_y.clamp(PADDLE_BOTTOM_BOUND, PADDLE_TOP_BOUND) - paddle_y;
+    let limit = (max_speed / PADDLE_SPEED).clamp(0., 1.);
+    (distance / (PADDLE_SPEED * timestep)).clamp(-limit, limit)
+}
+
+/// World y of the first touch, or else the cursor, while either is over the window
+fn pointer_y(
+    window: &Window,
+    touches: &Touches,
+    camera: &Camera,
+    camera_transform: &GlobalTransform,
+) -> Option<f32> {
+    let position = touches
+        .iter()
+        .next()
+        .map(|touch| touch.position())
+        .or_else(|| window.cursor_position())?;
+    camera
+        .viewport_to_world_2d(camera_transform, position)
+        .map(|world| world.y)
+}
+
+// Steers the paddle of the player picked in the settings towards the mouse or touch, in place
+// of their keys and gamepad. The paddle heads for the last position seen while the pointer is
+// away from the window.
+fn read_pointer_input(
+    settings: Res<Settings>,
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    touches: Res<Touches>,
+    camera_query: Query<(&Camera, &GlobalTransform)>,
+    mut paddle_query: Query<
+        (&mut PaddleInput, &Transform, &Player, Option<&Seat>),
+        With<KeyboardControl>,
+    >,
+    time: Res<Time<Fixed>>,
+    mut target: Local<Option<f32>>,
+) {
+    let Some(pointer_player) = settings.pointer_control else {
+        *target = None;
+        return;
+    };
+    if let (Ok(window), Ok((camera, camera_transform))) =
+        (window_query.get_single(), camera_query.get_single())
+    {
+        if let Some(y) = pointer_y(window, &touches, camera, camera_transform) {
+            *target = Some(y);
+        }
+    }
+    let Some(target_y) = *target else {
+        return;
+    };
+
+    for (mut input, transform, player, seat) in &mut paddle_query {
+        if seat_of(*player, seat) == pointer_player {
+            input.0 = pointer_direction(
+                transform.translation.y,
+                target_y,
+                settings.pointer_max_speed,
+                time.timestep().as_secs_f32(),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TICK: f32 = 1. / 64.;
+
+    #[test]
+    fn paddle_stops_on_the_pointer_when_it_is_near() {
+        let step = PADDLE_SPEED * TICK;
+
+        assert_eq!(pointer_direction(0., step / 2., PADDLE_SPEED, TICK), 0.5);
+        assert_eq!(pointer_direction(10., 10., PADDLE_SPEED, TICK), 0.);
+    }
+
+    #[test]
+    fn paddle_follows_a_far_pointer_at_the_max_speed() {
+        assert_eq!(pointer_direction(0., 200., PADDLE_SPEED, TICK), 1.);
+        assert_eq!(pointer_direction(0., -200., PADDLE_SPEED / 2., TICK), -0.5);
+        // never faster than keyboard players
+        assert_eq!(pointer_direction(0., 200., PADDLE_SPEED * 2., TICK), 1.);
+    }
+
+    #[test]
+    fn paddle_stays_within_the_walls() {
+        let direction = pointer_direction(
+            PADDLE_TOP_BOUND,
+            PADDLE_TOP_BOUND + 100.,
+            PADDLE_SPEED,
+            TICK,
+        );
+
+        assert_eq!(direction, 0.);
+    }
+}
@@ -0,0 +1,631 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, ErrorKind},
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ai::AiDifficulty,
+    match_::{process_score, Ball, KeyboardControl, Player, RoundState, Velocity},
+    rules::MatchRules,
+    GameState, MatchInfo, MatchMode, RoundData, Scores,
+};
+
+/// Longest profile name, in characters
+pub const MAX_NAME_LEN: usize = 16;
+
+/// Rating new profiles start from
+pub const START_RATING: f32 = 1500.;
+
+/// Most rating points one match can move
+const RATING_K: f32 = 32.;
+
+pub fn profiles_plugin(app: &mut App) {
+    app.init_resource::<MatchTally>()
+        .add_systems(OnEnter(GameState::Match), start_tally)
+        .add_systems(OnEnter(RoundState::In), start_rally)
+        .add_systems(OnEnter(RoundState::Scored), end_rally)
+        .add_systems(
+            FixedUpdate,
+            track_ball_speed
+                .after(process_score)
+                .run_if(in_state(RoundState::In)),
+        )
+        .add_systems(OnExit(GameState::Match), record_match);
+}
+
+/// Matches or points won and lost
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(default)]
+pub struct Record {
+    pub won: usize,
+    pub lost: usize,
+}
+
+impl Record {
+    fn add(&mut self, won: bool) {
+        if won {
+            self.won += 1;
+        } else {
+            self.lost += 1;
+        }
+    }
+}
+
+/// Lifetime statistics of a profile, over the matches it played to the end
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+#[serde(default)]
+pub struct Stats {
+    pub matches: Record,
+    pub points: Record,
+    /// Most paddle hits in one round
+    pub longest_rally: usize,
+    /// Fastest the ball went in any of the matches, in pixels per second
+    pub fastest_ball: f32,
+    pub vs_ai: BTreeMap<AiDifficulty, Record>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Profile {
+    pub name: String,
+    #[serde(default)]
+    pub stats: Stats,
+    /// Elo rating from the matches against other profiles
+    #[serde(default = "start_rating")]
+    pub rating: f32,
+}
+
+fn start_rating() -> f32 {
+    START_RATING
+}
+
+/// Ratings of the winner and the loser of a match after it, by the Elo system
+pub fn elo(winner: f32, loser: f32) -> (f32, f32) {
+    let expected = 1. / (1. + 10f32.powf((loser - winner) / 400.));
+    let change = RATING_K * (1. - expected);
+    (winner + change, loser - change)
+}
+
+/// A finished match with a profile on either side, kept to audit the ratings
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct MatchRecord {
+    /// When the match ended, in seconds since the Unix epoch
+    pub time: u64,
+    /// Profiles of Player A and B, None for a guest, the AI or the network opponent
+    pub a: Option<String>,
+    pub b: Option<String>,
+    pub vs_ai: Option<AiDifficulty>,
+    pub winner: Player,
+    pub games: (usize, usize),
+    /// Points over all games
+    pub points: (usize, usize),
+    /// Ratings of Player A and B after the match, if it was rated
+    pub ratings: Option<(f32, f32)>,
+}
+
+/// Why a profile can't be created
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum NameError {
+    Empty,
+    /// Another profile has that name
+    Taken,
+}
+
+/// Named players, and which of them play as Player A and B
+#[derive(Resource, Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
+#[serde(default)]
+pub struct Profiles {
+    pub profiles: Vec<Profile>,
+    /// Names of the profiles playing as Player A and B, None for a guest. The extra players
+    /// of a four-player match always play as guests.
+    pub a: Option<String>,
+    pub b: Option<String>,
+    /// Finished matches with a profile on either side, oldest first
+    pub history: Vec<MatchRecord>,
+}
+
+impl Profiles {
+    pub fn selected(&self, player: Player) -> Option<&str> {
+        match player {
+            Player::A => self.a.as_deref(),
+            Player::B => self.b.as_deref(),
+            Player::C | Player::D => None,
+        }
+    }
+
+    fn selected_mut(&mut self, player: Player) -> Option<&mut Option<String>> {
+        match player {
+            Player::A => Some(&mut self.a),
+            Player::B => Some(&mut self.b),
+            Player::C | Player::D => None,
+        }
+    }
+
+    /// Plays as the profile named `name` as `player`, or as a guest if there is none
+    pub fn select(&mut self, player: Player, name: &str) {
+        let profile = self
+            .profiles
+            .iter()
+            .find(|profile| profile.name == name)
+            .map(|profile| profile.name.clone());
+        if let Some(selected) = self.selected_mut(player) {
+            *selected = profile;
+        }
+    }
+
+    /// Creates a profile named `name` without its surrounding spaces
+    pub fn add(&mut self, name: &str) -> Result<(), NameError> {
+        let name: String = name.trim().chars().take(MAX_NAME_LEN).collect();
+        if name.is_empty() {
+            return Err(NameError::Empty);
+        }
+        if self.profiles.iter().any(|profile| profile.name == name) {
+            return Err(NameError::Taken);
+        }
+        self.profiles.push(Profile {
+            name,
+            stats: default(),
+            rating: START_RATING,
+        });
+        Ok(())
+    }
+
+    /// Switches `player` to the next profile the other player isn't using, then back to a guest
+    pub fn cycle(&mut self, player: Player) {
+        let other = self.selected(player.opponent()).map(str::to_string);
+        let choices: Vec<Option<String>> = std::iter::once(None)
+            .chain(
+                self.profiles
+                    .iter()
+                    .map(|profile| Some(profile.name.clone()))
+                    .filter(|name| *name != other),
+            )
+            .collect();
+        let Some(current) = self.selected_mut(player) else {
+            return;
+        };
+        let next = choices
+            .iter()
+            .position(|choice| choice == current)
+            .map_or(0, |i| (i + 1) % choices.len());
+        *current = choices[next].clone();
+    }
+
+    /// Adds a finished match to the stats of the profiles of the `local` players and to the
+    /// history. It is rated when both players are local with a profile each.
+    pub fn finish_match(
+        &mut self,
+        local: &[Player],
+        tally: &MatchTally,
+        winner: Player,
+        vs_ai: Option<AiDifficulty>,
+        games: (usize, usize),
+        time: u64,
+    ) {
+        for &player in local {
+            self.record_match(player, tally, winner, vs_ai);
+        }
+        let ratings = if local.len() == 2 {
+            self.rate(winner)
+        } else {
+            None
+        };
+        let profile = |player| {
+            local
+                .contains(&player)
+                .then(|| self.selected(player).map(str::to_string))
+                .flatten()
+        };
+        let (a, b) = (profile(Player::A), profile(Player::B));
+        if a.is_none() && b.is_none() {
+            return;
+        }
+        self.history.push(MatchRecord {
+            time,
+            a,
+            b,
+            vs_ai,
+            winner,
+            games,
+            points: tally.points,
+            ratings,
+        });
+    }
+
+    // Moves the ratings of the profiles of Player A and B by the result, None unless both
+    // players have a different profile
+    fn rate(&mut self, winner: Player) -> Option<(f32, f32)> {
+        let (a, b) = (self.a.clone()?, self.b.clone()?);
+        if a == b {
+            return None;
+        }
+        let rating = |name: &str| {
+            self.profiles
+                .iter()
+                .find(|profile| profile.name == name)
+                .map(|profile| profile.rating)
+        };
+        let (rating_a, rating_b) = (rating(&a)?, rating(&b)?);
+        let ratings = match winner {
+            Player::A => elo(rating_a, rating_b),
+            Player::B => {
+                let (new_b, new_a) = elo(rating_b, rating_a);
+                (new_a, new_b)
+            }
+            Player::C | Player::D => return None,
+        };
+        for profile in &mut self.profiles {
+            if profile.name == a {
+                profile.rating = ratings.0;
+            } else if profile.name == b {
+                profile.rating = ratings.1;
+            }
+        }
+        Some(ratings)
+    }
+
+    /// Ratings of the profile named `name` from the start, one after each rated match
+    pub fn rating_history(&self, name: &str) -> Vec<f32> {
+        let ratings = self.history.iter().filter_map(|record| {
+            let (a, b) = record.ratings?;
+            if record.a.as_deref() == Some(name) {
+                Some(a)
+            } else if record.b.as_deref() == Some(name) {
+                Some(b)
+            } else {
+                None
+            }
+        });
+        std::iter::once(START_RATING).chain(ratings).collect()
+    }
+
+    /// Profiles by rating, best first
+    pub fn leaderboard(&self) -> Vec<&Profile> {
+        let mut profiles: Vec<&Profile> = self.profiles.iter().collect();
+        profiles.sort_by(|a, b| b.rating.total_cmp(&a.rating));
+        profiles
+    }
+
+    /// Adds a finished match to the stats of the profile playing as `player`, if any
+    fn record_match(
+        &mut self,
+        player: Player,
+        tally: &MatchTally,
+        winner: Player,
+        vs_ai: Option<AiDifficulty>,
+    ) {
+        let Some(name) = self.selected(player).map(str::to_string) else {
+            return;
+        };
+        let Some(profile) = self
+            .profiles
+            .iter_mut()
+            .find(|profile| profile.name == name)
+        else {
+            return;
+        };
+        let stats = &mut profile.stats;
+        let won = winner == player;
+        stats.matches.add(won);
+        let (points_won, points_lost) = match player {
+            Player::A => tally.points,
+            Player::B => (tally.points.1, tally.points.0),
+            Player::C | Player::D => return,
+        };
+        stats.points.won += points_won;
+        stats.points.lost += points_lost;
+        stats.longest_rally = stats.longest_rally.max(tally.longest_rally);
+        stats.fastest_ball = stats.fastest_ball.max(tally.fastest_ball);
+        if let Some(difficulty) = vs_ai {
+            stats.vs_ai.entry(difficulty).or_default().add(won);
+        }
+    }
+}
+
+/// File profiles are loaded from and saved to. Without it, as in simulated matches, there are
+/// no profiles and nothing is saved.
+#[derive(Resource)]
+pub struct ProfilesFile(pub PathBuf);
+
+impl Default for ProfilesFile {
+    fn default() -> Self {
+        let dir = dirs::data_dir().unwrap_or_default().join("pong-bevy");
+        ProfilesFile(dir.join("profiles.ron"))
+    }
+}
+
+impl ProfilesFile {
+    /// Saved profiles, or none if there are none or they can't be read
+    pub fn load(&self) -> Profiles {
+        let text = match fs::read_to_string(&self.0) {
+            Ok(text) => text,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Profiles::default(),
+            Err(err) => {
+                warn!("can't read profiles from {}: {}", self.0.display(), err);
+                return Profiles::default();
+            }
+        };
+        ron::from_str(&text).unwrap_or_else(|err| {
+            warn!("ignoring corrupt profiles in {}: {}", self.0.display(), err);
+            Profiles::default()
+        })
+    }
+
+    pub fn save(&self, profiles: &Profiles) {
+        let text = match ron::ser::to_string_pretty(profiles, default()) {
+            Ok(text) => text,
+            Err(err) => {
+                warn!("can't encode profiles: {}", err);
+                return;
+            }
+        };
+        let written = match self.0.parent() {
+            Some(dir) => fs::create_dir_all(dir).and_then(|_| fs::write(&self.0, text)),
+            None => fs::write(&self.0, text),
+        };
+        if let Err(err) = written {
+            warn!("can't save profiles to {}: {}", self.0.display(), err);
+        }
+    }
+
+    /// Writes the match history next to the profiles, as match-history.csv and
+    /// match-history.json, and returns the folder they are in
+    pub fn export_history(&self, profiles: &Profiles) -> io::Result<PathBuf> {
+        let dir = self.0.parent().map(PathBuf::from).unwrap_or_default();
+        fs::create_dir_all(&dir)?;
+        fs::write(
+            dir.join("match-history.csv"),
+            history_csv(&profiles.history),
+        )?;
+        fs::write(
+            dir.join("match-history.json"),
+            serde_json::to_string_pretty(&profiles.history)?,
+        )?;
+        Ok(dir)
+    }
+}
+
+/// Match history as CSV, a header line and then one line per match
+pub fn history_csv(history: &[MatchRecord]) -> String {
+    // names are typed by players, they may hold commas or quotes
+    fn field(value: &str) -> String {
+        if value.contains([',', '"', '\n']) {
+            format!("\"{}\"", value.replace('"', "\"\""))
+        } else {
+            value.to_string()
+        }
+    }
+    let rating = |rating: Option<f32>| rating.map_or(String::new(), |r| format!("{:.1}", r));
+
+    let mut csv = String::from(
+        "time,player_a,player_b,ai,winner,games_a,games_b,points_a,points_b,rating_a,rating_b\n",
+    );
+    for record in history {
+        csv.push_str(&format!(
+            "{},{},{},{},{:?},{},{},{},{},{},{}\n",
+            record.time,
+            field(record.a.as_deref().unwrap_or("")),
+            field(record.b.as_deref().unwrap_or("")),
+            record.vs_ai.map_or(String::new(), |ai| format!("{:?}", ai)),
+            record.winner,
+            record.games.0,
+            record.games.1,
+            record.points.0,
+            record.points.1,
+            rating(record.ratings.map(|(a, _)| a)),
+            rating(record.ratings.map(|(_, b)| b)),
+        ));
+    }
+    csv
+}
+
+pub fn save_profiles(profiles: Res<Profiles>, file: Option<Res<ProfilesFile>>) {
+    if let Some(file) = file {
+        file.save(&profiles);
+    }
+}
+
+/// What happened in the current match, added to the profiles when it ends
+#[derive(Resource, Default, Debug)]
+pub struct MatchTally {
+    /// Points scored by Player A and B over all games
+    pub points: (usize, usize),
+    pub longest_rally: usize,
+    pub fastest_ball: f32,
+    // paddle hits of the match when the round started
+    rally_start: usize,
+    // scores when the round started, a point with extra balls can have several goals
+    scores_at_start: (usize, usize),
+}
+
+fn start_tally(mut tally: ResMut<MatchTally>) {
+    *tally = MatchTally::default();
+}
+
+fn start_rally(mut tally: ResMut<MatchTally>, round_data: Res<RoundData>, scores: Res<Scores>) {
+    tally.rally_start = round_data.paddle_hit_count;
+    tally.scores_at_start = (scores.a, scores.b);
+}
+
+fn end_rally(mut tally: ResMut<MatchTally>, round_data: Res<RoundData>, scores: Res<Scores>) {
+    let rally = round_data
+        .paddle_hit_count
+        .saturating_sub(tally.rally_start);
+    tally.longest_rally = tally.longest_rally.max(rally);
+    let (a, b) = tally.scores_at_start;
+    tally.points.0 += scores.a.saturating_sub(a);
+    tally.points.1 += scores.b.saturating_sub(b);
+}
+
+fn track_ball_speed(mut tally: ResMut<MatchTally>, ball_query: Query<&Velocity, With<Ball>>) {
+    for velocity in &ball_query {
+        tally.fastest_ball = tally.fastest_ball.max(velocity.length());
+    }
+}
+
+// Only matches played to the end count, and only for the players on this machine
+fn record_match(
+    mut profiles: ResMut<Profiles>,
+    file: Option<Res<ProfilesFile>>,
+    tally: Res<MatchTally>,
+    match_: Res<MatchInfo>,
+    rules: Res<MatchRules>,
+    match_mode: Res<MatchMode>,
+    local_query: Query<&Player, With<KeyboardControl>>,
+) {
+    let Some(winner) = rules.match_winner(match_.games_a, match_.games_b) else {
+        return;
+    };
+    // the stats and ratings are of one-on-one matches
+    if let MatchMode::Doubles(_) = *match_mode {
+        return;
+    }
+    let vs_ai = match *match_mode {
+        MatchMode::VsAi(difficulty) => Some(difficulty),
+        _ => None,
+    };
+    let local: Vec<Player> = local_query.iter().copied().collect();
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    profiles.finish_match(
+        &local,
+        &tally,
+        winner,
+        vs_ai,
+        (match_.games_a, match_.games_b),
+        time,
+    );
+    if let Some(file) = file {
+        file.save(&profiles);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profiles(names: &[&str]) -> Profiles {
+        let mut profiles = Profiles::default();
+        for name in names {
+            profiles.add(name).unwrap();
+        }
+        profiles
+    }
+
+    #[test]
+    fn profile_names_are_trimmed_and_unique() {
+        let mut profiles = profiles(&["Ada"]);
+
+        assert_eq!(profiles.add("  Ada "), Err(NameError::Taken));
+        assert_eq!(profiles.add("   "), Err(NameError::Empty));
+        assert_eq!(profiles.add(" Grace Hopper "), Ok(()));
+        assert_eq!(profiles.profiles[1].name, "Grace Hopper");
+    }
+
+    #[test]
+    fn players_cycle_through_the_profiles_the_other_is_not_using() {
+        let mut profiles = profiles(&["Ada", "Grace", "Linus"]);
+        profiles.b = Some("Grace".to_string());
+
+        let mut picked = Vec::new();
+        for _ in 0..3 {
+            profiles.cycle(Player::A);
+            picked.push(profiles.a.clone());
+        }
+
+        assert_eq!(
+            picked,
+            vec![Some("Ada".to_string()), Some("Linus".to_string()), None]
+        );
+    }
+
+    #[test]
+    fn upsets_move_the_ratings_more() {
+        let (winner, loser) = elo(START_RATING, START_RATING);
+        assert_eq!((winner, loser), (START_RATING + 16., START_RATING - 16.));
+
+        let (favourite, _) = elo(1700., 1500.);
+        let (underdog, _) = elo(1500., 1700.);
+        assert!(favourite - 1700. < 16.);
+        assert!(underdog - 1500. > 16.);
+    }
+
+    #[test]
+    fn only_local_matches_between_two_profiles_are_rated() {
+        let mut profiles = profiles(&["Ada", "Grace"]);
+        profiles.a = Some("Ada".to_string());
+        profiles.b = Some("Grace".to_string());
+        let tally = MatchTally::default();
+
+        profiles.finish_match(&[Player::A, Player::B], &tally, Player::B, None, (0, 1), 10);
+        // against the AI, or over the network
+        profiles.finish_match(&[Player::A], &tally, Player::A, None, (1, 0), 20);
+        profiles.a = None;
+        profiles.finish_match(&[Player::A, Player::B], &tally, Player::A, None, (1, 0), 30);
+
+        assert_eq!(profiles.profiles[0].rating, START_RATING - 16.);
+        assert_eq!(profiles.profiles[1].rating, START_RATING + 16.);
+        assert_eq!(profiles.rating_history("Grace"), vec![1500., 1516.]);
+        assert_eq!(profiles.history.len(), 3);
+        assert_eq!(profiles.history[1].b, None);
+        assert_eq!(profiles.history[2].a, None);
+        assert_eq!(profiles.leaderboard()[0].name, "Grace");
+    }
+
+    #[test]
+    fn history_exports_as_csv() {
+        let history = vec![MatchRecord {
+            time: 1700000000,
+            a: Some("Smith, J".to_string()),
+            b: None,
+            vs_ai: Some(AiDifficulty::Hard),
+            winner: Player::B,
+            games: (0, 1),
+            points: (2, 5),
+            ratings: None,
+        }];
+
+        let csv = history_csv(&history);
+
+        assert_eq!(
+            csv.lines().nth(1),
+            Some("1700000000,\"Smith, J\",,Hard,B,0,1,2,5,,")
+        );
+        assert_eq!(csv.lines().count(), 2);
+    }
+
+    #[test]
+    fn finished_matches_add_up_in_the_stats() {
+        let mut profiles = profiles(&["Ada", "Grace"]);
+        profiles.a = Some("Ada".to_string());
+        profiles.b = Some("Grace".to_string());
+        let tally = MatchTally {
+            points: (5, 3),
+            longest_rally: 12,
+            fastest_ball: 900.,
+            ..default()
+        };
+
+        profiles.record_match(Player::A, &tally, Player::A, Some(AiDifficulty::Hard));
+        profiles.record_match(Player::A, &tally, Player::B, Some(AiDifficulty::Hard));
+        profiles.record_match(Player::B, &tally, Player::A, None);
+
+        let ada = &profiles.profiles[0].stats;
+        assert_eq!(ada.matches, Record { won: 1, lost: 1 });
+        assert_eq!(ada.points, Record { won: 10, lost: 6 });
+        assert_eq!(ada.longest_rally, 12);
+        assert_eq!(ada.fastest_ball, 900.);
+        assert_eq!(ada.vs_ai[&AiDifficulty::Hard], Record { won: 1, lost: 1 });
+        let grace = &profiles.profiles[1].stats;
+        assert_eq!(grace.matches, Record { won: 0, lost: 1 });
+        assert_eq!(grace.points, Record { won: 3, lost: 5 });
+        assert!(grace.vs_ai.is_empty());
+    }
+}
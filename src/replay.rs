@@ -0,0 +1,711 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bevy::{ecs::system::RunSystemOnce, prelude::*};
+
+use crate::{
+    ai::move_ai_paddle,
+    countdown::setup_countdown,
+    match_::{
+        move_paddle, process_score, setup_match, Ball, InGoal, OnMatchView, Paddle, PaddleInput,
+        PaddleVelocity, Player, RoundState, RoundTick, ServeRng, Simulation, Spin, Velocity,
+    },
+    net::{push_rules, read_rules, Reader},
+    rules::{ExtraBalls, MatchRules},
+    CollisionEvent, GameState, MatchInfo, MatchMode, RoundData, ScoreEvent, Scores,
+    BALL_START_POSITION, TEXT_COLOR,
+};
+
+const REPLAY_MAGIC: [u8; 4] = *b"PNGR";
+const REPLAY_VERSION: u8 = 1;
+
+/// Replay files kept, older ones are deleted as new matches are recorded
+const MAX_SAVED_REPLAYS: usize = 50;
+
+/// Playback speeds picked from with the up and down arrows
+const REPLAY_SPEEDS: [f32; 6] = [0.25, 0.5, 1., 2., 4., 8.];
+
+/// Ticks the left and right arrows seek by, two seconds at the default timestep
+const SEEK_TICKS: u32 = 128;
+
+/// Ticks a round plays on past its recorded inputs before a recording cut short ends
+const END_GRACE_TICKS: usize = 64;
+
+/// Longest round a replay file is trusted with, an hour at the default timestep
+const MAX_ROUND_TICKS: usize = 64 * 60 * 60;
+
+pub fn replay_plugin(app: &mut App) {
+    app.add_systems(
+        OnEnter(GameState::Match),
+        (
+            start_recording,
+            start_replay.run_if(resource_exists::<ReplayPlayer>),
+        )
+            .after(setup_match),
+    )
+    .add_systems(
+        OnExit(GameState::Match),
+        (
+            save_recording
+                .run_if(resource_exists::<Recording>.and_then(resource_exists::<ReplayDir>)),
+            stop_replay.run_if(resource_exists::<ReplayPlayer>),
+        ),
+    )
+    .add_systems(
+        OnEnter(RoundState::In),
+        record_round.run_if(resource_exists::<Recording>),
+    )
+    .add_systems(
+        OnEnter(RoundState::Countdown),
+        start_replay_round
+            .after(setup_countdown)
+            .run_if(resource_exists::<ReplayPlayer>),
+    )
+    .add_systems(
+        OnEnter(RoundState::Scored),
+        next_replay_round.run_if(resource_exists::<ReplayPlayer>),
+    )
+    .add_systems(
+        Simulation,
+        (
+            record_inputs.run_if(resource_exists::<Recording>),
+            play_back_inputs.run_if(resource_exists::<ReplayPlayer>),
+        )
+            .after(move_ai_paddle)
+            .before(move_paddle),
+    )
+    .add_systems(
+        Update,
+        (replay_controls, seek_replay, update_replay_status)
+            .chain()
+            .run_if(resource_exists::<ReplayPlayer>.and_then(in_state(GameState::Match))),
+    );
+}
+
+/// Everything needed to play a match again: its rules and seed, and for each round the state
+/// it started from and the paddle inputs of every tick
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Replay {
+    pub seed: u64,
+    pub rules: MatchRules,
+    pub rounds: Vec<ReplayRound>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct ReplayRound {
+    pub server: Player,
+    pub serve: Vec2,
+    pub scores: (usize, usize),
+    pub games: (usize, usize),
+    /// Paddle hits of the match before this round, every third one speeds the ball up
+    pub paddle_hit_count: usize,
+    /// Inputs of the paddles of Player A and B on each tick of the round
+    pub inputs: Vec<[f32; 2]>,
+}
+
+impl Replay {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = REPLAY_MAGIC.to_vec();
+        bytes.push(REPLAY_VERSION);
+        bytes.extend(self.seed.to_le_bytes());
+        push_rules(&mut bytes, &self.rules);
+        bytes.extend((self.rounds.len() as u32).to_le_bytes());
+        for round in &self.rounds {
+            bytes.push(match round.server {
+                Player::A => 0,
+                Player::B => 1,
+                Player::C => 2,
+                Player::D => 3,
+            });
+            bytes.extend(round.serve.x.to_le_bytes());
+            bytes.extend(round.serve.y.to_le_bytes());
+            for value in [round.scores.0, round.scores.1, round.games.0, round.games.1] {
+                bytes.extend((value as u16).to_le_bytes());
+            }
+            bytes.extend((round.paddle_hit_count as u32).to_le_bytes());
+
+            // paddles mostly keep their input for many ticks, each run is stored once
+            let runs = round.inputs.chunk_by(|a, b| a == b);
+            bytes.extend((runs.clone().count() as u32).to_le_bytes());
+            for run in runs {
+                bytes.extend((run.len() as u32).to_le_bytes());
+                bytes.extend(run[0][0].to_le_bytes());
+                bytes.extend(run[0][1].to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<Replay> {
+        let mut reader = Reader(bytes.strip_prefix(&REPLAY_MAGIC)?);
+        if reader.u8()? != REPLAY_VERSION {
+            return None;
+        }
+        let seed = reader.u64()?;
+        let rules = read_rules(&mut reader)?;
+        let round_count = reader.u32()?;
+        let mut rounds = Vec::new();
+        for _ in 0..round_count {
+            let server = match reader.u8()? {
+                0 => Player::A,
+                1 => Player::B,
+                2 => Player::C,
+                3 => Player::D,
+                _ => return None,
+            };
+            let serve = Vec2::new(reader.f32()?, reader.f32()?);
+            let scores = (reader.u16()? as usize, reader.u16()? as usize);
+            let games = (reader.u16()? as usize, reader.u16()? as usize);
+            let paddle_hit_count = reader.u32()? as usize;
+            let mut inputs = Vec::new();
+            for _ in 0..reader.u32()? {
+                let len = reader.u32()? as usize;
+                let input = [reader.f32()?, reader.f32()?];
+                if inputs.len() + len > MAX_ROUND_TICKS {
+                    return None;
+                }
+                inputs.extend(std::iter::repeat_n(input, len));
+            }
+            rounds.push(ReplayRound {
+                server,
+                serve,
+                scores,
+                games,
+                paddle_hit_count,
+                inputs,
+            });
+        }
+        Some(Replay {
+            seed,
+            rules,
+            rounds,
+        })
+    }
+}
+
+/// Folder matches are recorded to. Without it, as in simulated matches, nothing is saved.
+#[derive(Resource)]
+pub struct ReplayDir(pub PathBuf);
+
+impl Default for ReplayDir {
+    fn default() -> Self {
+        ReplayDir(
+            dirs::data_dir()
+                .unwrap_or_default()
+                .join("pong-bevy/replays"),
+        )
+    }
+}
+
+impl ReplayDir {
+    /// Replay files with when they were recorded, newest first
+    pub fn list(&self) -> Vec<(PathBuf, SystemTime)> {
+        let Ok(entries) = fs::read_dir(&self.0) else {
+            return Vec::new();
+        };
+        let mut replays: Vec<_> = entries
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "replay"))
+            .filter_map(|path| {
+                let modified = fs::metadata(&path).and_then(|meta| meta.modified()).ok()?;
+                Some((path, modified))
+            })
+            .collect();
+        replays.sort_by_key(|&(_, modified)| std::cmp::Reverse(modified));
+        replays
+    }
+
+    pub fn save(&self, replay: &Replay) -> io::Result<PathBuf> {
+        fs::create_dir_all(&self.0)?;
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let path = self.0.join(format!("match-{}.replay", millis));
+        fs::write(&path, replay.encode())?;
+
+        for (old, _) in self.list().into_iter().skip(MAX_SAVED_REPLAYS) {
+            if let Err(err) = fs::remove_file(&old) {
+                warn!("can't delete old replay {}: {}", old.display(), err);
+            }
+        }
+        Ok(path)
+    }
+}
+
+/// Replay in the file at `path`, None if it can't be read
+pub fn load_replay(path: &Path) -> Option<Replay> {
+    let bytes = fs::read(path)
+        .map_err(|err| warn!("can't read replay {}: {}", path.display(), err))
+        .ok()?;
+    let replay = Replay::decode(&bytes);
+    if replay.is_none() {
+        warn!("ignoring corrupt replay {}", path.display());
+    }
+    replay
+}
+
+/// Match being recorded, or the last one recorded once it is over
+#[derive(Resource, Deref, DerefMut)]
+pub struct Recording(pub Replay);
+
+/// Recorded match being played back
+#[derive(Resource)]
+pub struct ReplayPlayer {
+    pub replay: Replay,
+    /// Round being played or counted down to
+    pub round: usize,
+    speed: usize,
+    // tick of the round to jump to
+    seek: Option<u32>,
+    // rules picked by the player, put back when the replay ends
+    player_rules: Option<MatchRules>,
+}
+
+impl ReplayPlayer {
+    pub fn new(replay: Replay) -> ReplayPlayer {
+        ReplayPlayer {
+            replay,
+            round: 0,
+            speed: REPLAY_SPEEDS.iter().position(|&speed| speed == 1.).unwrap(),
+            seek: None,
+            player_rules: None,
+        }
+    }
+}
+
+fn start_recording(
+    mut commands: Commands,
+    match_: Res<MatchInfo>,
+    rules: Res<MatchRules>,
+    match_mode: Res<MatchMode>,
+) {
+    // four-player and doubles matches aren't recorded, replays only have the inputs of two
+    // paddles, and seeking only puts one ball back
+    if matches!(
+        *match_mode,
+        MatchMode::Replay | MatchMode::FourPlayer | MatchMode::Doubles(_)
+    ) || rules.extra_balls != ExtraBalls::Off
+    {
+        commands.remove_resource::<Recording>();
+        return;
+    }
+    commands.insert_resource(Recording(Replay {
+        seed: match_.seed,
+        rules: *rules,
+        rounds: Vec::new(),
+    }));
+}
+
+fn record_round(
+    mut recording: ResMut<Recording>,
+    match_: Res<MatchInfo>,
+    scores: Res<Scores>,
+    round_data: Res<RoundData>,
+) {
+    recording.rounds.push(ReplayRound {
+        server: match_.server,
+        serve: match_.serve,
+        scores: (scores.a, scores.b),
+        games: (match_.games_a, match_.games_b),
+        paddle_hit_count: round_data.paddle_hit_count,
+        inputs: Vec::new(),
+    });
+}
+
+fn record_inputs(
+    mut recording: ResMut<Recording>,
+    tick: Res<RoundTick>,
+    query: Query<(&PaddleInput, &Player), With<Paddle>>,
+) {
+    let Some(round) = recording.rounds.last_mut() else {
+        return;
+    };
+    let mut inputs = [0.; 2];
+    for (input, player) in &query {
+        match player {
+            Player::A => inputs[0] = input.0,
+            Player::B => inputs[1] = input.0,
+            Player::C | Player::D => {}
+        }
+    }
+    // ticks a network match simulates again replace what was recorded for them
+    round.inputs.resize(tick.0 as usize, [0.; 2]);
+    round.inputs.push(inputs);
+}
+
+fn save_recording(recording: Res<Recording>, dir: Res<ReplayDir>) {
+    if recording.rounds.is_empty() {
+        return;
+    }
+    match dir.save(&recording) {
+        Ok(path) => info!("match recorded to {}", path.display()),
+        Err(err) => warn!("can't save replay to {}: {}", dir.0.display(), err),
+    }
+}
+
+#[derive(Component)]
+struct ReplayStatusText;
+
+// Plays the match by the recorded rules and seed, with the player's rules kept aside
+fn start_replay(
+    mut commands: Commands,
+    mut player: ResMut<ReplayPlayer>,
+    mut rules: ResMut<MatchRules>,
+    mut match_: ResMut<MatchInfo>,
+) {
+    player.player_rules = Some(*rules);
+    *rules = player.replay.rules;
+    match_.seed = player.replay.seed;
+    commands.insert_resource(ServeRng(fastrand::Rng::with_seed(match_.seed)));
+
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 20.0,
+                color: TEXT_COLOR,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(10.0),
+            left: Val::Px(10.0),
+            ..default()
+        }),
+        ReplayStatusText,
+        OnMatchView,
+    ));
+}
+
+fn stop_replay(
+    mut commands: Commands,
+    player: Res<ReplayPlayer>,
+    mut rules: ResMut<MatchRules>,
+    mut time: ResMut<Time<Virtual>>,
+) {
+    if let Some(player_rules) = player.player_rules {
+        *rules = player_rules;
+    }
+    time.unpause();
+    time.set_relative_speed(1.);
+    commands.remove_resource::<ReplayPlayer>();
+}
+
+/// Sets the match up as it was when the recorded round started
+fn restore_round(
+    round: &ReplayRound,
+    match_: &mut MatchInfo,
+    scores: &mut Scores,
+    round_data: &mut RoundData,
+) {
+    match_.server = round.server;
+    match_.serve = round.serve;
+    (scores.a, scores.b) = round.scores;
+    (match_.games_a, match_.games_b) = round.games;
+    round_data.paddle_hit_count = round.paddle_hit_count;
+}
+
+fn start_replay_round(
+    player: Res<ReplayPlayer>,
+    mut match_: ResMut<MatchInfo>,
+    mut scores: ResMut<Scores>,
+    mut round_data: ResMut<RoundData>,
+    mut next_game_state: ResMut<NextState<GameState>>,
+) {
+    match player.replay.rounds.get(player.round) {
+        Some(round) => {
+            match_.round_count = player.round;
+            restore_round(round, &mut match_, &mut scores, &mut round_data);
+        }
+        // the recording stopped between rounds
+        None => next_game_state.set(GameState::Menu),
+    }
+}
+
+fn next_replay_round(mut player: ResMut<ReplayPlayer>) {
+    player.round += 1;
+}
+
+fn play_back_inputs(
+    player: Res<ReplayPlayer>,
+    tick: Res<RoundTick>,
+    mut query: Query<(&mut PaddleInput, &Player), With<Paddle>>,
+) {
+    let inputs = player
+        .replay
+        .rounds
+        .get(player.round)
+        .and_then(|round| round.inputs.get(tick.0 as usize))
+        .copied()
+        .unwrap_or_default();
+    for (mut input, player) in &mut query {
+        input.0 = match player {
+            Player::A => inputs[0],
+            Player::B => inputs[1],
+            Player::C | Player::D => 0.,
+        };
+    }
+}
+
+// Space pauses, up and down change the speed, left and right seek through the round, page up
+// and page down go to the previous and next round, and escape leaves the replay
+fn replay_controls(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut player: ResMut<ReplayPlayer>,
+    mut time: ResMut<Time<Virtual>>,
+    tick: Res<RoundTick>,
+    round_state: Res<State<RoundState>>,
+    mut next_round_state: ResMut<NextState<RoundState>>,
+    mut next_game_state: ResMut<NextState<GameState>>,
+    mut match_: ResMut<MatchInfo>,
+    mut scores: ResMut<Scores>,
+    mut round_data: ResMut<RoundData>,
+) {
+    if keyboard_input.just_pressed(KeyCode::Escape) {
+        next_game_state.set(GameState::Menu);
+        return;
+    }
+
+    if keyboard_input.just_pressed(KeyCode::Space) {
+        if time.is_paused() {
+            time.unpause();
+        } else {
+            time.pause();
+        }
+    }
+    if keyboard_input.just_pressed(KeyCode::ArrowUp) {
+        player.speed = (player.speed + 1).min(REPLAY_SPEEDS.len() - 1);
+    }
+    if keyboard_input.just_pressed(KeyCode::ArrowDown) {
+        player.speed = player.speed.saturating_sub(1);
+    }
+    time.set_relative_speed(REPLAY_SPEEDS[player.speed]);
+
+    let round_state = *round_state.get();
+    if round_state == RoundState::In {
+        if keyboard_input.just_pressed(KeyCode::ArrowLeft) {
+            player.seek = Some(tick.0.saturating_sub(SEEK_TICKS));
+        }
+        if keyboard_input.just_pressed(KeyCode::ArrowRight) {
+            player.seek = Some(tick.0 + SEEK_TICKS);
+        }
+
+        let recorded = player
+            .replay
+            .rounds
+            .get(player.round)
+            .map_or(0, |round| round.inputs.len());
+        if tick.0 as usize > recorded + END_GRACE_TICKS {
+            info!("end of the recording");
+            next_game_state.set(GameState::Menu);
+        }
+    }
+
+    if matches!(round_state, RoundState::In | RoundState::Countdown) {
+        let last = player.replay.rounds.len().saturating_sub(1);
+        let round = if keyboard_input.just_pressed(KeyCode::PageUp) {
+            player.round.saturating_sub(1)
+        } else if keyboard_input.just_pressed(KeyCode::PageDown) {
+            (player.round + 1).min(last)
+        } else {
+            player.round
+        };
+        if round != player.round {
+            player.round = round;
+            player.seek = None;
+            if round_state == RoundState::Countdown {
+                // the ball and paddles are already in place
+                match_.round_count = round;
+                let round = &player.replay.rounds[round];
+                restore_round(round, &mut match_, &mut scores, &mut round_data);
+            } else {
+                next_round_state.set(RoundState::Countdown);
+            }
+        }
+    }
+}
+
+// Runs the round from its start, or on from the current tick, up to the tick sought. A goal on
+// the way ends the seek and is scored as usual.
+fn seek_replay(world: &mut World) {
+    let Some(target) = world.resource_mut::<ReplayPlayer>().seek.take() else {
+        return;
+    };
+    if *world.resource::<State<RoundState>>().get() != RoundState::In {
+        return;
+    }
+
+    if target < world.resource::<RoundTick>().0 {
+        let start = {
+            let player = world.resource::<ReplayPlayer>();
+            player
+                .replay
+                .rounds
+                .get(player.round)
+                .map(|round| (round.serve, round.paddle_hit_count))
+        };
+        let Some((serve, paddle_hit_count)) = start else {
+            return;
+        };
+
+        let mut ball_query = world
+            .query_filtered::<(Entity, &mut Transform, &mut Velocity, &mut Spin), With<Ball>>();
+        let mut balls = Vec::new();
+        for (ball, mut transform, mut velocity, mut spin) in ball_query.iter_mut(world) {
+            transform.translation = BALL_START_POSITION;
+            velocity.0 = serve;
+            spin.0 = 0.;
+            balls.push(ball);
+        }
+        // back in play if the seek came right after the goal
+        for ball in balls {
+            world.entity_mut(ball).remove::<InGoal>();
+        }
+        let mut paddle_query = world.query_filtered::<
+            (&mut Transform, &mut PaddleVelocity, &Player),
+            (With<Paddle>, Without<Ball>),
+        >();
+        for (mut transform, mut velocity, player) in paddle_query.iter_mut(world) {
+            transform.translation = player.start_position();
+            velocity.0 = 0.;
+        }
+        world.resource_mut::<RoundData>().paddle_hit_count = paddle_hit_count;
+        world.resource_mut::<RoundTick>().0 = 0;
+    }
+
+    let goals = world.resource::<Events<ScoreEvent>>().len();
+    while world.resource::<RoundTick>().0 < target {
+        world.run_schedule(Simulation);
+        world.resource_mut::<RoundTick>().0 += 1;
+        if world.resource::<Events<ScoreEvent>>().len() > goals {
+            world.run_system_once(process_score);
+            break;
+        }
+    }
+    // no burst of collision sounds for the skipped ticks
+    world.resource_mut::<Events<CollisionEvent>>().clear();
+}
+
+fn update_replay_status(
+    player: Res<ReplayPlayer>,
+    tick: Res<RoundTick>,
+    time: Res<Time<Virtual>>,
+    fixed_time: Res<Time<Fixed>>,
+    mut query: Query<&mut Text, With<ReplayStatusText>>,
+) {
+    let seconds = tick.0 as f32 * fixed_time.timestep().as_secs_f32();
+    let state = if time.is_paused() {
+        "paused".to_string()
+    } else {
+        format!("x{}", REPLAY_SPEEDS[player.speed])
+    };
+    for mut text in &mut query {
+        text.sections[0].value = format!(
+            "Replay  |  point {}/{}  |  {:.1} s  |  {}\n\
+             Space pause, Up/Down speed, Left/Right seek, PgUp/PgDn point, Esc quit",
+            (player.round + 1).min(player.replay.rounds.len()),
+            player.replay.rounds.len(),
+            seconds,
+            state,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ai::AiDifficulty,
+        headless::{headless_app, play_match},
+    };
+
+    fn round(inputs: Vec<[f32; 2]>) -> ReplayRound {
+        ReplayRound {
+            server: Player::B,
+            serve: Vec2::new(-800., 12.5),
+            scores: (3, 1),
+            games: (0, 1),
+            paddle_hit_count: 7,
+            inputs,
+        }
+    }
+
+    #[test]
+    fn replays_survive_encoding() {
+        let replay = Replay {
+            seed: 0xDEAD_BEEF_1234,
+            rules: MatchRules {
+                deuce_cap: Some(3),
+                spin: true,
+                ..default()
+            },
+            rounds: vec![
+                round(vec![[1., 0.], [1., 0.], [0.25, -1.], [1., 0.]]),
+                round(Vec::new()),
+            ],
+        };
+
+        assert_eq!(Replay::decode(&replay.encode()), Some(replay));
+    }
+
+    #[test]
+    fn held_inputs_are_stored_once() {
+        let held = Replay {
+            rounds: vec![round(vec![[1., -1.]; 1000])],
+            ..default()
+        };
+        let changing = Replay {
+            rounds: vec![round(vec![[1., -1.], [0., -1.]])],
+            ..default()
+        };
+
+        assert!(held.encode().len() < changing.encode().len());
+        assert_eq!(Replay::decode(&held.encode()), Some(held));
+    }
+
+    #[test]
+    fn corrupt_replays_are_rejected() {
+        let mut bytes = Replay::default().encode();
+        bytes[4] = REPLAY_VERSION + 1;
+
+        assert_eq!(Replay::decode(&bytes), None);
+        assert_eq!(Replay::decode(&REPLAY_MAGIC), None);
+    }
+
+    #[test]
+    fn replays_play_out_like_the_recorded_match() {
+        let mut app = headless_app();
+        app.add_plugins(replay_plugin);
+        // let startup load the default rules first
+        app.update();
+        app.insert_resource(MatchRules {
+            points_to_win: 3,
+            ..default()
+        });
+
+        let recorded = play_match(
+            &mut app,
+            MatchMode::AiVsAi(AiDifficulty::Hard, AiDifficulty::Easy),
+            None,
+            64 * 60 * 10,
+        )
+        .expect("recorded match did not end");
+        let replay = app.world.resource::<Recording>().0.clone();
+        assert_eq!(replay.rounds.len(), recorded.rounds);
+
+        // the replay brings its own rules
+        app.insert_resource(MatchRules::default());
+        app.insert_resource(ReplayPlayer::new(Replay::decode(&replay.encode()).unwrap()));
+        let replayed = play_match(&mut app, MatchMode::Replay, None, 64 * 60 * 10)
+            .expect("replay did not end");
+
+        assert_eq!(replayed, recorded);
+        assert_eq!(*app.world.resource::<MatchRules>(), MatchRules::default());
+    }
+}
@@ -0,0 +1,635 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+
+use crate::{
+    match_::{
+        Ball, InGoal, KeyboardControl, Paddle, PaddleInput, Player, RoundState, RoundTick,
+        Simulation, Spin, Velocity,
+    },
+    net::RemoteControl,
+    CollisionEvent, GameState, MatchMode, RoundData, ScoreEvent, Scores,
+};
+
+/// How far back, in ticks, a late remote input can still be corrected
+const MAX_ROLLBACK_TICKS: usize = 128;
+
+/// Max inputs sent in one packet
+pub const MAX_INPUTS_PER_PACKET: usize = 64;
+
+pub fn rollback_plugin(app: &mut App) {
+    app.add_event::<RemoteInputs>()
+        .add_systems(OnEnter(GameState::Match), start_rollback)
+        .add_systems(OnExit(GameState::Match), stop_rollback)
+        .add_systems(
+            OnEnter(RoundState::In),
+            start_round.run_if(resource_exists::<Rollback>),
+        )
+        .add_systems(
+            PreUpdate,
+            receive_inputs.run_if(resource_exists::<Rollback>),
+        );
+}
+
+/// Inputs of the peer for ticks `first_tick..` of its round `round`, along with how many
+/// of our inputs it has received
+#[derive(Event)]
+pub struct RemoteInputs {
+    pub round: u16,
+    pub ack: u32,
+    pub first_tick: u32,
+    pub directions: Vec<i8>,
+}
+
+/// Paddle inputs travel as a byte so both peers simulate with the exact same value
+pub fn quantize(direction: f32) -> i8 {
+    (direction.clamp(-1., 1.) * 127.).round() as i8
+}
+
+pub fn dequantize(direction: i8) -> f32 {
+    direction as f32 / 127.
+}
+
+/// Simulation state at the start of a tick
+#[derive(Clone, Debug)]
+struct SimSnapshot {
+    tick: u32,
+    ball_translation: Vec3,
+    ball_velocity: Vec2,
+    ball_spin: f32,
+    ball_in_goal: bool,
+    paddle_a: Vec3,
+    paddle_b: Vec3,
+    scores: (usize, usize),
+    paddle_hit_count: usize,
+    pending_score: Option<(u32, ScoreEvent)>,
+}
+
+/// Input history and state snapshots of the current round of a network match.
+///
+/// Both machines simulate the round. The remote paddle is predicted to keep its last known
+/// input, and when its actual input for a past tick turns out different, the simulation is
+/// restored to that tick and run again up to the present.
+#[derive(Resource)]
+pub struct Rollback {
+    /// Rounds started in this match, tags inputs so they are not mixed up between rounds
+    pub round: u16,
+    /// Next tick to simulate, counted from the start of the round
+    pub tick: u32,
+    local_inputs: Vec<i8>,
+    remote_inputs: Vec<Option<i8>>,
+    // remote inputs the simulation ran with, confirmed or predicted
+    used_remote_inputs: Vec<i8>,
+    /// Number of leading remote inputs received, all ticks before this are final
+    pub remote_confirmed: u32,
+    /// Number of local inputs the peer has received
+    pub remote_ack: u32,
+    snapshots: VecDeque<SimSnapshot>,
+    rollback_from: Option<u32>,
+    // goal in the simulation, waiting for the remote inputs that led to it to be confirmed
+    pending_score: Option<(u32, ScoreEvent)>,
+    // only the host decides scores, the client waits to hear about them
+    commits_scores: bool,
+    // a goal was handed to process_score, nothing is simulated until the next round
+    round_over: bool,
+}
+
+impl Rollback {
+    fn new(commits_scores: bool) -> Rollback {
+        Rollback {
+            round: 0,
+            tick: 0,
+            local_inputs: Vec::new(),
+            remote_inputs: Vec::new(),
+            used_remote_inputs: Vec::new(),
+            remote_confirmed: 0,
+            remote_ack: 0,
+            snapshots: VecDeque::with_capacity(MAX_ROLLBACK_TICKS),
+            rollback_from: None,
+            pending_score: None,
+            commits_scores,
+            round_over: false,
+        }
+    }
+
+    /// Local inputs the peer has not acknowledged yet
+    pub fn unacked_inputs(&self) -> (u32, &[i8]) {
+        let first = (self.remote_ack as usize).min(self.local_inputs.len());
+        let last = self.local_inputs.len().min(first + MAX_INPUTS_PER_PACKET);
+        (first as u32, &self.local_inputs[first..last])
+    }
+
+    fn confirm_remote(&mut self, tick: u32, direction: i8) {
+        let index = tick as usize;
+        if self.remote_inputs.len() <= index {
+            self.remote_inputs.resize(index + 1, None);
+        }
+        if self.remote_inputs[index].is_some() {
+            return;
+        }
+        self.remote_inputs[index] = Some(direction);
+
+        while self
+            .remote_inputs
+            .get(self.remote_confirmed as usize)
+            .is_some_and(Option::is_some)
+        {
+            self.remote_confirmed += 1;
+        }
+
+        // already simulated with a wrong guess
+        if self
+            .used_remote_inputs
+            .get(index)
+            .is_some_and(|&used| used != direction)
+        {
+            self.rollback_from = Some(self.rollback_from.map_or(tick, |from| from.min(tick)));
+        }
+    }
+
+    /// The remote input for `tick`, or the last one known before it
+    fn remote_input(&self, tick: u32) -> i8 {
+        self.remote_inputs[..self.remote_inputs.len().min(tick as usize + 1)]
+            .iter()
+            .rev()
+            .find_map(|&direction| direction)
+            .unwrap_or(0)
+    }
+}
+
+fn start_rollback(mut commands: Commands, match_mode: Res<MatchMode>) {
+    match *match_mode {
+        MatchMode::NetworkHost => commands.insert_resource(Rollback::new(true)),
+        MatchMode::NetworkClient => commands.insert_resource(Rollback::new(false)),
+        _ => {}
+    }
+}
+
+fn stop_rollback(mut commands: Commands) {
+    commands.remove_resource::<Rollback>();
+}
+
+fn start_round(mut rollback: ResMut<Rollback>) {
+    let round = rollback.round + 1;
+    *rollback = Rollback {
+        round,
+        ..Rollback::new(rollback.commits_scores)
+    };
+}
+
+fn receive_inputs(mut rollback: ResMut<Rollback>, mut received: EventReader<RemoteInputs>) {
+    for inputs in received.read() {
+        if inputs.round != rollback.round {
+            continue;
+        }
+        // ticks come from the network, a forged packet must not overflow them or grow the
+        // input history without bound
+        let Some(end) = inputs
+            .first_tick
+            .checked_add(inputs.directions.len() as u32)
+        else {
+            continue;
+        };
+        rollback.remote_ack = rollback.remote_ack.max(inputs.ack);
+        // the peer stalls before it gets further ahead than this
+        let last_tick = rollback.tick.saturating_add(MAX_ROLLBACK_TICKS as u32);
+        for (tick, &direction) in (inputs.first_tick..end).zip(&inputs.directions) {
+            if tick > last_tick {
+                break;
+            }
+            rollback.confirm_remote(tick, direction);
+        }
+    }
+}
+
+/// Runs one tick of a network match, rolling back first if a late input proved a
+/// prediction wrong. Waits for the peer instead while its inputs lag so far behind that a
+/// late one could no longer be rolled back to.
+pub fn advance(world: &mut World) {
+    let (rollback_from, tick) = {
+        let mut rollback = world.resource_mut::<Rollback>();
+        if rollback.round_over
+            || rollback.tick.saturating_sub(rollback.remote_confirmed) >= MAX_ROLLBACK_TICKS as u32
+        {
+            return;
+        }
+        (rollback.rollback_from.take(), rollback.tick)
+    };
+
+    if let Some(from) = rollback_from {
+        let snapshot = world
+            .resource::<Rollback>()
+            .snapshots
+            .iter()
+            .find(|snapshot| snapshot.tick == from)
+            .cloned();
+        match snapshot {
+            Some(snapshot) => {
+                debug!("rolling back {} ticks", tick - from);
+                restore(world, &snapshot);
+                world
+                    .resource_mut::<Rollback>()
+                    .snapshots
+                    .retain(|snapshot| snapshot.tick < from);
+                for tick in from..tick {
+                    step(world, tick);
+                }
+                // sounds of the resimulated ticks were already played
+                world.resource_mut::<Events<CollisionEvent>>().clear();
+            }
+            None => warn!("input for tick {} arrived too late to roll back", from),
+        }
+    }
+
+    let mut local_query = world.query_filtered::<&PaddleInput, With<KeyboardControl>>();
+    let local = local_query
+        .get_single(world)
+        .map_or(0, |input| quantize(input.0));
+    world.resource_mut::<Rollback>().local_inputs.push(local);
+
+    step(world, tick);
+
+    let mut rollback = world.resource_mut::<Rollback>();
+    rollback.tick += 1;
+
+    // hand the goal over to process_score once it can't be undone anymore
+    if let Some((score_tick, scorer)) = rollback.pending_score {
+        if rollback.commits_scores && rollback.remote_confirmed > score_tick {
+            rollback.pending_score = None;
+            rollback.round_over = true;
+            // the one ball of a network match is in the goal
+            world.resource_mut::<RoundData>().balls_in_play = 0;
+            world.send_event(scorer);
+        }
+    }
+}
+
+fn step(world: &mut World, tick: u32) {
+    let snapshot = save(world, tick);
+    let (local, remote) = {
+        let mut rollback = world.resource_mut::<Rollback>();
+        if rollback.snapshots.len() == MAX_ROLLBACK_TICKS {
+            rollback.snapshots.pop_front();
+        }
+        rollback.snapshots.push_back(snapshot);
+
+        let remote = rollback.remote_input(tick);
+        let index = tick as usize;
+        if rollback.used_remote_inputs.len() <= index {
+            rollback.used_remote_inputs.resize(index + 1, 0);
+        }
+        rollback.used_remote_inputs[index] = remote;
+        (rollback.local_inputs[index], remote)
+    };
+
+    let mut input_query = world.query_filtered::<
+        (&mut PaddleInput, Has<KeyboardControl>, Has<RemoteControl>),
+        With<Paddle>,
+    >();
+    for (mut input, is_local, is_remote) in input_query.iter_mut(world) {
+        if is_local {
+            input.0 = dequantize(local);
+        } else if is_remote {
+            input.0 = dequantize(remote);
+        }
+    }
+
+    world.resource_mut::<RoundTick>().0 = tick;
+    world.run_schedule(Simulation);
+    world.resource_mut::<RoundTick>().0 = tick + 1;
+
+    // goals are held back instead of going straight to process_score
+    let scorer = world.resource_mut::<Events<ScoreEvent>>().drain().next();
+    let mut rollback = world.resource_mut::<Rollback>();
+    if let (Some(scorer), None) = (scorer, rollback.pending_score) {
+        rollback.pending_score = Some((tick, scorer));
+    }
+}
+
+fn save(world: &mut World, tick: u32) -> SimSnapshot {
+    let mut ball_query =
+        world.query_filtered::<(&Transform, &Velocity, &Spin, Has<InGoal>), With<Ball>>();
+    let (ball_translation, ball_velocity, ball_spin, ball_in_goal) =
+        ball_query.get_single(world).map_or(
+            (Vec3::ZERO, Vec2::ZERO, 0., false),
+            |(transform, velocity, spin, in_goal)| {
+                (transform.translation, velocity.0, spin.0, in_goal)
+            },
+        );
+
+    let mut paddle_a = Vec3::ZERO;
+    let mut paddle_b = Vec3::ZERO;
+    let mut paddle_query = world.query_filtered::<(&Transform, &Player), With<Paddle>>();
+    for (transform, player) in paddle_query.iter(world) {
+        match player {
+            Player::A => paddle_a = transform.translation,
+            Player::B => paddle_b = transform.translation,
+            // only two-player network matches roll back
+            Player::C | Player::D => {}
+        }
+    }
+
+    let scores = world.resource::<Scores>();
+    SimSnapshot {
+        tick,
+        ball_translation,
+        ball_velocity,
+        ball_spin,
+        ball_in_goal,
+        paddle_a,
+        paddle_b,
+        scores: (scores.a, scores.b),
+        paddle_hit_count: world.resource::<RoundData>().paddle_hit_count,
+        pending_score: world.resource::<Rollback>().pending_score,
+    }
+}
+
+fn restore(world: &mut World, snapshot: &SimSnapshot) {
+    let mut ball_query =
+        world.query_filtered::<(Entity, &mut Transform, &mut Velocity, &mut Spin), With<Ball>>();
+    if let Ok((ball, mut transform, mut velocity, mut spin)) = ball_query.get_single_mut(world) {
+        transform.translation = snapshot.ball_translation;
+        velocity.0 = snapshot.ball_velocity;
+        spin.0 = snapshot.ball_spin;
+        if snapshot.ball_in_goal {
+            world.entity_mut(ball).insert(InGoal);
+        } else {
+            world.entity_mut(ball).remove::<InGoal>();
+        }
+    }
+
+    let mut paddle_query =
+        world.query_filtered::<(&mut Transform, &Player), (With<Paddle>, Without<Ball>)>();
+    for (mut transform, player) in paddle_query.iter_mut(world) {
+        transform.translation = match player {
+            Player::A => snapshot.paddle_a,
+            Player::B => snapshot.paddle_b,
+            Player::C | Player::D => continue,
+        };
+    }
+
+    let mut scores = world.resource_mut::<Scores>();
+    scores.a = snapshot.scores.0;
+    scores.b = snapshot.scores.1;
+    world.resource_mut::<RoundData>().paddle_hit_count = snapshot.paddle_hit_count;
+    world.resource_mut::<Rollback>().pending_score = snapshot.pending_score;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{headless::headless_app, net::Message, MatchInfo, MatchSeed, RIGHT_WALL};
+
+    /// Headless app with a network match started as `match_mode`, waiting in the countdown
+    fn network_app(match_mode: MatchMode) -> App {
+        let mut app = headless_app();
+        app.add_plugins(rollback_plugin);
+        app.update();
+        app.insert_resource(match_mode);
+        app.insert_resource(MatchSeed(Some(7)));
+        app.world
+            .resource_mut::<NextState<GameState>>()
+            .set(GameState::Match);
+        app.update();
+        app
+    }
+
+    fn round_state(app: &App) -> RoundState {
+        *app.world.resource::<State<RoundState>>().get()
+    }
+
+    fn rollback(app: &App) -> &Rollback {
+        app.world.resource::<Rollback>()
+    }
+
+    /// Updates the host until it serves
+    fn start_round(app: &mut App) {
+        for _ in 0..64 * 5 {
+            if round_state(app) == RoundState::In {
+                return;
+            }
+            app.update();
+        }
+        panic!("round did not start");
+    }
+
+    fn set_key(app: &mut App, key: KeyCode, pressed: bool) {
+        let mut keyboard_input = app.world.resource_mut::<ButtonInput<KeyCode>>();
+        if pressed {
+            keyboard_input.press(key);
+        } else {
+            keyboard_input.release(key);
+        }
+    }
+
+    /// Ball translation and velocity, then the translations of the paddles of A and B
+    fn sim_state(app: &mut App) -> (Vec3, Vec2, Vec3, Vec3) {
+        let mut ball_query = app
+            .world
+            .query_filtered::<(&Transform, &Velocity), With<Ball>>();
+        let (ball, velocity) = ball_query.single(&app.world);
+        let (ball, velocity) = (ball.translation, velocity.0);
+
+        let mut paddles = [Vec3::ZERO; 2];
+        let mut paddle_query = app
+            .world
+            .query_filtered::<(&Transform, &Player), With<Paddle>>();
+        for (transform, player) in paddle_query.iter(&app.world) {
+            paddles[player.index()] = transform.translation;
+        }
+        (ball, velocity, paddles[0], paddles[1])
+    }
+
+    /// Hands the inputs `from` has not had acknowledged to `to`, through the wire format
+    fn send_inputs(from: &App, to: &mut App) {
+        let Some(rollback) = from.world.get_resource::<Rollback>() else {
+            return;
+        };
+        let (first_tick, directions) = rollback.unacked_inputs();
+        let message = Message::Inputs {
+            round: rollback.round,
+            ack: rollback.remote_confirmed,
+            first_tick,
+            directions: directions.to_vec(),
+        };
+        let Some(Message::Inputs {
+            round,
+            ack,
+            first_tick,
+            directions,
+        }) = Message::decode(&message.encode())
+        else {
+            panic!("inputs did not survive encoding");
+        };
+        to.world.send_event(RemoteInputs {
+            round,
+            ack,
+            first_tick,
+            directions,
+        });
+    }
+
+    /// Has the client follow the host into its rounds, as the host's status would
+    fn follow_host(host: &App, client: &mut App) {
+        let serve = host.world.resource::<MatchInfo>().serve;
+        client.world.resource_mut::<MatchInfo>().serve = serve;
+        let round = round_state(host);
+        if round_state(client) != round {
+            client
+                .world
+                .resource_mut::<NextState<RoundState>>()
+                .set(round);
+        }
+    }
+
+    /// Remote inputs of the host's round for ticks `0..count`, sent as the peer would
+    fn send_remote_inputs(app: &mut App, directions: &[i8], count: usize) {
+        let round = rollback(app).round;
+        app.world.send_event(RemoteInputs {
+            round,
+            ack: 0,
+            first_tick: 0,
+            directions: directions[..count.min(directions.len())].to_vec(),
+        });
+    }
+
+    #[test]
+    fn host_and_client_play_out_the_same_round() {
+        let mut host = network_app(MatchMode::NetworkHost);
+        let mut client = network_app(MatchMode::NetworkClient);
+        start_round(&mut host);
+
+        // each peer steers its paddle for a while, then lets go
+        for tick in 0..15 {
+            set_key(&mut host, KeyCode::KeyW, tick < 10);
+            set_key(&mut client, KeyCode::ArrowDown, tick < 10);
+            follow_host(&host, &mut client);
+            client.update();
+            host.update();
+            send_inputs(&host, &mut client);
+            send_inputs(&client, &mut host);
+        }
+        assert_eq!(round_state(&client), RoundState::In);
+
+        // the client joined the round an update late
+        for _ in 0..4 {
+            if rollback(&client).tick >= rollback(&host).tick {
+                break;
+            }
+            client.update();
+            send_inputs(&client, &mut host);
+        }
+        assert_eq!(rollback(&client).tick, rollback(&host).tick);
+        for _ in 0..5 {
+            client.update();
+            host.update();
+            send_inputs(&host, &mut client);
+            send_inputs(&client, &mut host);
+        }
+
+        let state = sim_state(&mut host);
+        assert!(state.2.y > 0. && state.3.y < 0., "paddles did not move");
+        assert_eq!(sim_state(&mut client), state);
+    }
+
+    #[test]
+    fn late_inputs_roll_back_to_the_on_time_result() {
+        // the remote paddle goes up for ten ticks
+        let directions: Vec<i8> = (0..30)
+            .map(|tick| if (2..12).contains(&tick) { 127 } else { 0 })
+            .collect();
+
+        let play = |delay: usize| {
+            let mut app = network_app(MatchMode::NetworkHost);
+            start_round(&mut app);
+            let mut predicted_b = Vec::new();
+            while (rollback(&app).tick as usize) < directions.len() {
+                let tick = rollback(&app).tick as usize;
+                send_remote_inputs(&mut app, &directions, (tick + 1).saturating_sub(delay));
+                app.update();
+                predicted_b.push(sim_state(&mut app).3.y);
+            }
+            (sim_state(&mut app), predicted_b)
+        };
+
+        let (on_time, _) = play(0);
+        let (late, predicted_b) = play(8);
+        // the paddle was predicted to stay put until its inputs arrived
+        assert_eq!(predicted_b[5], 0.);
+        assert!(on_time.3.y > 0.);
+        assert_eq!(late, on_time);
+    }
+
+    #[test]
+    fn goals_wait_for_the_inputs_that_led_to_them() {
+        let mut app = network_app(MatchMode::NetworkHost);
+        start_round(&mut app);
+        let mut ball_query = app
+            .world
+            .query_filtered::<(&mut Transform, &mut Velocity), With<Ball>>();
+        let (mut transform, mut velocity) = ball_query.single_mut(&mut app.world);
+        transform.translation = Vec3::new(RIGHT_WALL - 30., 250., 1.);
+        velocity.0 = Vec2::new(800., 0.);
+
+        for _ in 0..5 {
+            app.update();
+        }
+        let (score_tick, scorer) = rollback(&app).pending_score.expect("no goal");
+        assert_eq!(scorer, ScoreEvent::A);
+        assert_eq!(round_state(&app), RoundState::In);
+
+        // the peer's input for the tick of the goal is still missing
+        let directions = vec![0; rollback(&app).tick as usize];
+        send_remote_inputs(&mut app, &directions, score_tick as usize);
+        app.update();
+        app.update();
+        assert_eq!(rollback(&app).remote_confirmed, score_tick);
+        assert_eq!(round_state(&app), RoundState::In);
+        assert_eq!(app.world.resource::<Scores>().a, 0);
+
+        send_remote_inputs(&mut app, &directions, directions.len());
+        app.update();
+        app.update();
+        assert_eq!(round_state(&app), RoundState::Scored);
+        assert_eq!(app.world.resource::<Scores>().a, 1);
+    }
+
+    #[test]
+    fn inputs_for_ticks_far_ahead_are_ignored() {
+        let mut app = network_app(MatchMode::NetworkHost);
+        start_round(&mut app);
+        let round = rollback(&app).round;
+        // the first would overflow the tick count, the second would fill gigabytes
+        for first_tick in [u32::MAX - 1, u32::MAX / 2] {
+            app.world.send_event(RemoteInputs {
+                round,
+                ack: 0,
+                first_tick,
+                directions: vec![127; 4],
+            });
+        }
+        app.update();
+
+        assert!(rollback(&app).remote_inputs.is_empty());
+        assert_eq!(rollback(&app).remote_confirmed, 0);
+    }
+
+    #[test]
+    fn peers_wait_for_inputs_older_than_the_rollback_window() {
+        let mut app = network_app(MatchMode::NetworkHost);
+        start_round(&mut app);
+        for _ in 0..MAX_ROLLBACK_TICKS + 10 {
+            app.update();
+        }
+        assert_eq!(rollback(&app).tick, MAX_ROLLBACK_TICKS as u32);
+        let predicted_b = sim_state(&mut app).3;
+
+        // the peer held its paddle up from the start
+        let directions = vec![127; MAX_ROLLBACK_TICKS];
+        send_remote_inputs(&mut app, &directions, directions.len());
+        app.update();
+
+        assert_eq!(rollback(&app).tick, MAX_ROLLBACK_TICKS as u32 + 1);
+        assert!(sim_state(&mut app).3.y > predicted_b.y);
+    }
+}
@@ -0,0 +1,187 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::match_::Player;
+
+/// Who serves the round after a point
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ServeRule {
+    /// The player who scored serves, to the one who was scored on
+    Winner,
+    Loser,
+    /// Players take turns whoever scored
+    Alternate,
+}
+
+/// When balls are added to a point in play
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ExtraBalls {
+    /// One ball a point
+    Off,
+    /// A new ball every few seconds of play
+    Timed,
+    /// A ball running into a power-up splits in two
+    PowerUp,
+}
+
+/// Rules matches are played by, picked on the screen before the match
+#[derive(Resource, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(default)]
+pub struct MatchRules {
+    /// Points needed to win a game
+    pub points_to_win: usize,
+    /// Games in the match, it is won by the first player to win most of them
+    pub best_of: usize,
+    /// Lead in points needed to win a game, 1 to win on points_to_win alone
+    pub win_by: usize,
+    /// Points past points_to_win at which a game is won without the win_by lead
+    pub deuce_cap: Option<usize>,
+    pub serve: ServeRule,
+    /// Most degrees off the horizontal a serve can go, picked at random for each serve
+    pub serve_cone: usize,
+    /// Degrees off the horizontal the ball leaves a paddle at when it hits the paddle's edge,
+    /// hits closer to the centre leave flatter
+    pub max_bounce_angle: usize,
+    /// Whether a moving paddle spins the ball it returns, curving its path
+    pub spin: bool,
+    /// Goals a player can let in before they are out of a four-player match
+    pub lives: usize,
+    /// Extra balls joining the point, which goes on until all of them are in a goal
+    pub extra_balls: ExtraBalls,
+}
+
+impl Default for MatchRules {
+    fn default() -> Self {
+        MatchRules {
+            points_to_win: 2,
+            best_of: 1,
+            win_by: 2,
+            deuce_cap: None,
+            serve: ServeRule::Winner,
+            serve_cone: 30,
+            max_bounce_angle: 45,
+            spin: false,
+            lives: 3,
+            extra_balls: ExtraBalls::Off,
+        }
+    }
+}
+
+impl MatchRules {
+    /// Winner of a game standing at `score_a` to `score_b`, None while it goes on
+    pub fn game_winner(&self, score_a: usize, score_b: usize) -> Option<Player> {
+        let (leader, points, opponent_points) = match score_a.cmp(&score_b) {
+            std::cmp::Ordering::Greater => (Player::A, score_a, score_b),
+            std::cmp::Ordering::Less => (Player::B, score_b, score_a),
+            std::cmp::Ordering::Equal => return None,
+        };
+        if points < self.points_to_win {
+            return None;
+        }
+        let capped = self
+            .deuce_cap
+            .is_some_and(|cap| points >= self.points_to_win + cap);
+        (points - opponent_points >= self.win_by || capped).then_some(leader)
+    }
+
+    /// Winner of the match with `games_a` and `games_b` won, None while it goes on
+    pub fn match_winner(&self, games_a: usize, games_b: usize) -> Option<Player> {
+        let games_to_win = self.best_of / 2 + 1;
+        if games_a >= games_to_win {
+            Some(Player::A)
+        } else if games_b >= games_to_win {
+            Some(Player::B)
+        } else {
+            None
+        }
+    }
+
+    /// Player serving after `scorer` won a point served by `server`
+    pub fn next_server(&self, server: Player, scorer: Player) -> Player {
+        match self.serve {
+            ServeRule::Winner => scorer,
+            ServeRule::Loser => scorer.opponent(),
+            ServeRule::Alternate => server.opponent(),
+        }
+    }
+
+    /// Short summary such as "first to 11, win by 2, best of 3"
+    pub fn describe(&self) -> String {
+        let mut description = format!("first to {}", self.points_to_win);
+        if self.win_by > 1 {
+            description += &format!(", win by {}", self.win_by);
+        }
+        if let Some(cap) = self.deuce_cap {
+            description += &format!(" up to {}", self.points_to_win + cap);
+        }
+        if self.best_of > 1 {
+            description += &format!(", best of {}", self.best_of);
+        }
+        description
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn game_needs_points_to_win_and_the_lead() {
+        let rules = MatchRules {
+            points_to_win: 11,
+            win_by: 2,
+            ..default()
+        };
+
+        assert_eq!(rules.game_winner(10, 3), None);
+        assert_eq!(rules.game_winner(11, 3), Some(Player::A));
+        assert_eq!(rules.game_winner(11, 10), None);
+        assert_eq!(rules.game_winner(15, 13), Some(Player::A));
+        assert_eq!(rules.game_winner(12, 14), Some(Player::B));
+        assert_eq!(rules.game_winner(12, 12), None);
+    }
+
+    #[test]
+    fn deuce_cap_ends_close_games() {
+        let rules = MatchRules {
+            points_to_win: 11,
+            win_by: 2,
+            deuce_cap: Some(2),
+            ..default()
+        };
+
+        assert_eq!(rules.game_winner(12, 12), None);
+        assert_eq!(rules.game_winner(13, 12), Some(Player::A));
+    }
+
+    #[test]
+    fn match_goes_to_whoever_wins_most_games() {
+        let rules = MatchRules {
+            best_of: 3,
+            ..default()
+        };
+
+        assert_eq!(rules.match_winner(1, 1), None);
+        assert_eq!(rules.match_winner(2, 1), Some(Player::A));
+        assert_eq!(rules.match_winner(0, 2), Some(Player::B));
+        assert_eq!(MatchRules::default().match_winner(0, 1), Some(Player::B));
+    }
+
+    #[test]
+    fn serve_follows_the_serve_rule() {
+        let serve = |serve| MatchRules { serve, ..default() };
+
+        assert_eq!(
+            serve(ServeRule::Winner).next_server(Player::A, Player::B),
+            Player::B
+        );
+        assert_eq!(
+            serve(ServeRule::Loser).next_server(Player::A, Player::B),
+            Player::A
+        );
+        assert_eq!(
+            serve(ServeRule::Alternate).next_server(Player::A, Player::A),
+            Player::B
+        );
+    }
+}
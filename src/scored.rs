@@ -1,26 +1,224 @@
+use std::collections::VecDeque;
+
 use bevy::prelude::*;
 
 use crate::{
-    despawn_screen, match_::RoundState, spawn_timed_message, GameState, GameTimer, MatchInfo,
-    ScoreEvent, Scores,
+    despawn_screen,
+    doubles::{seat_of, Seat},
+    four_player::Lives,
+    input_map::{Action, InputMap},
+    is_match_authority,
+    match_::{match_running, process_score, Ball, Paddle, Player, RoundState},
+    menu::key_name,
+    rules::{ExtraBalls, MatchRules},
+    spawn_timed_message, GameState, GameTimer, MatchInfo, MatchMode, ScoreEvent, Scores,
+    TEXT_COLOR,
 };
 
+/// Ticks of play kept for the instant replay, a second and a half at the default timestep
+const POINT_HISTORY_TICKS: usize = 96;
+
+/// Speed the end of the point is replayed at
+const INSTANT_REPLAY_SPEED: f32 = 0.4;
+
 pub fn scored_plugin(app: &mut App) {
-    app.add_systems(Update, run_scored.run_if(in_state(RoundState::Scored)))
-        .add_systems(OnEnter(RoundState::Scored), setup_scored)
-        .add_systems(OnExit(RoundState::Scored), despawn_screen::<OnScoredScreen>);
+    app.init_resource::<PointHistory>()
+        .add_systems(
+            Update,
+            (
+                // four-player matches count lives instead
+                run_scored.run_if(is_match_authority.and_then(not(resource_exists::<Lives>))),
+                play_instant_replay.run_if(resource_exists::<InstantReplay>),
+            )
+                .run_if(in_state(RoundState::Scored).and_then(match_running)),
+        )
+        .add_systems(
+            FixedUpdate,
+            record_point_history
+                .after(process_score)
+                .run_if(in_state(RoundState::In).and_then(match_running)),
+        )
+        .add_systems(OnEnter(RoundState::In), clear_point_history)
+        .add_systems(
+            OnEnter(RoundState::Scored),
+            (setup_scored, start_instant_replay),
+        )
+        .add_systems(
+            OnExit(RoundState::Scored),
+            (despawn_screen::<OnScoredScreen>, stop_instant_replay),
+        );
 }
 
 #[derive(Component, Clone)]
 struct OnScoredScreen;
 
-pub fn setup_scored(commands: Commands, mut score_events: EventReader<ScoreEvent>) {
-    let scorer = score_events.read().collect::<Vec<&ScoreEvent>>()[0];
-    let scorer_text = match scorer {
-        ScoreEvent::A => "A",
-        ScoreEvent::B => "B",
+/// Where the ball and paddles were on one tick
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct PointFrame {
+    ball: Vec3,
+    /// By `Player::index` of the player steering each paddle
+    paddles: [Vec3; 4],
+}
+
+impl PointFrame {
+    fn lerp(&self, next: &PointFrame, t: f32) -> PointFrame {
+        PointFrame {
+            ball: self.ball.lerp(next.ball, t),
+            paddles: std::array::from_fn(|i| self.paddles[i].lerp(next.paddles[i], t)),
+        }
+    }
+}
+
+/// The last ticks of the round being played
+#[derive(Resource, Default)]
+struct PointHistory(VecDeque<PointFrame>);
+
+/// Slow-motion replay of the end of the point, shown while the point is scored
+#[derive(Resource)]
+pub struct InstantReplay {
+    frames: Vec<PointFrame>,
+    // seconds of play replayed so far
+    elapsed: f32,
+}
+
+#[derive(Component)]
+struct InstantReplayText;
+
+fn clear_point_history(mut history: ResMut<PointHistory>) {
+    history.0.clear();
+}
+
+fn record_point_history(
+    mut history: ResMut<PointHistory>,
+    ball_query: Query<&Transform, With<Ball>>,
+    paddle_query: Query<(&Transform, &Player, Option<&Seat>), With<Paddle>>,
+) {
+    let Ok(ball) = ball_query.get_single() else {
+        return;
+    };
+    let mut frame = PointFrame {
+        ball: ball.translation,
+        paddles: [Vec3::ZERO; 4],
+    };
+    for (transform, player, seat) in &paddle_query {
+        frame.paddles[seat_of(*player, seat).index()] = transform.translation;
+    }
+    if history.0.len() == POINT_HISTORY_TICKS {
+        history.0.pop_front();
+    }
+    history.0.push_back(frame);
+}
+
+// Recorded matches are sought back through instead, and the frames only hold one ball
+fn start_instant_replay(
+    mut commands: Commands,
+    history: Res<PointHistory>,
+    input_map: Res<InputMap>,
+    match_mode: Res<MatchMode>,
+    rules: Res<MatchRules>,
+) {
+    if history.0.len() < 2
+        || *match_mode == MatchMode::Replay
+        || rules.extra_balls != ExtraBalls::Off
+    {
+        return;
+    }
+    let mut skip_keys = Vec::new();
+    for &player in match_mode.players() {
+        let key = key_name(input_map.key(player, Action::Serve));
+        if !skip_keys.contains(&key) {
+            skip_keys.push(key);
+        }
+    }
+    commands.insert_resource(InstantReplay {
+        frames: history.0.iter().copied().collect(),
+        elapsed: 0.,
+    });
+    commands.spawn((
+        TextBundle::from_section(
+            format!("Replay - {} to skip", skip_keys.join(" / ")),
+            TextStyle {
+                font_size: 24.0,
+                color: TEXT_COLOR,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(20.0),
+            left: Val::Px(20.0),
+            ..default()
+        }),
+        InstantReplayText,
+        OnScoredScreen,
+    ));
+}
+
+// Puts the ball and paddles where they were, slowed down, until the goal or until a player
+// presses their serve key. They end up where the point ended either way.
+fn play_instant_replay(
+    mut commands: Commands,
+    mut replay: ResMut<InstantReplay>,
+    time: Res<Time>,
+    fixed_time: Res<Time<Fixed>>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    input_map: Res<InputMap>,
+    match_mode: Res<MatchMode>,
+    mut ball_query: Query<&mut Transform, With<Ball>>,
+    mut paddle_query: Query<
+        (&mut Transform, &Player, Option<&Seat>),
+        (With<Paddle>, Without<Ball>),
+    >,
+    text_query: Query<Entity, With<InstantReplayText>>,
+) {
+    replay.elapsed += time.delta_seconds() * INSTANT_REPLAY_SPEED;
+    let position = replay.elapsed / fixed_time.timestep().as_secs_f32();
+    let index = position as usize;
+    let skipped = match_mode
+        .players()
+        .iter()
+        .any(|&player| keyboard_input.just_pressed(input_map.key(player, Action::Serve)));
+
+    let last = replay.frames.len() - 1;
+    let done = skipped || index >= last;
+    let frame = if done {
+        replay.frames[last]
+    } else {
+        replay.frames[index].lerp(&replay.frames[index + 1], position.fract())
+    };
+
+    for mut transform in &mut ball_query {
+        transform.translation = frame.ball;
+    }
+    for (mut transform, player, seat) in &mut paddle_query {
+        transform.translation = frame.paddles[seat_of(*player, seat).index()];
+    }
+
+    if done {
+        commands.remove_resource::<InstantReplay>();
+        for entity in &text_query {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+fn stop_instant_replay(mut commands: Commands) {
+    commands.remove_resource::<InstantReplay>();
+}
+
+pub fn setup_scored(
+    commands: Commands,
+    mut score_events: EventReader<ScoreEvent>,
+    match_mode: Res<MatchMode>,
+) {
+    let Some(scorer) = score_events.read().next() else {
+        return;
     };
-    let message = format!("Player {} scores!", scorer_text);
+    let scorer = match scorer {
+        ScoreEvent::A => Player::A,
+        ScoreEvent::B => Player::B,
+    };
+    let message = format!("{} scores!", match_mode.side_name(scorer));
     spawn_timed_message(commands, &message, 2.0, OnScoredScreen);
 }
 
@@ -31,25 +229,394 @@ pub fn run_scored(
     time: Res<Time>,
     mut timer: ResMut<GameTimer>,
     mut score_events: EventReader<ScoreEvent>,
-    scores: Res<Scores>,
+    mut scorer: Local<Option<Player>>,
+    mut scores: ResMut<Scores>,
+    rules: Res<MatchRules>,
+    instant_replay: Option<Res<InstantReplay>>,
 ) {
+    if let Some(event) = score_events.read().last() {
+        *scorer = Some(match event {
+            ScoreEvent::A => Player::A,
+            ScoreEvent::B => Player::B,
+        });
+    }
+    // the next round waits for the replay of this one
+    if instant_replay.is_some() {
+        return;
+    }
+
     if timer.tick(time.delta()).finished() {
-        score_events.clear();
+        let scorer = scorer.take();
         match_.round_count += 1;
 
-        println!("match {}/{}", match_.round_count, match_.rounds_total);
+        info!(
+            "round {}, score {} - {}, games {} - {}",
+            match_.round_count, scores.a, scores.b, match_.games_a, match_.games_b
+        );
 
-        if match_.round_count == match_.rounds_total {
-            if scores.a >= scores.b + 2 || scores.b >= scores.a + 2 {
+        if let Some(winner) = rules.game_winner(scores.a, scores.b) {
+            match winner {
+                Player::A => match_.games_a += 1,
+                Player::B => match_.games_b += 1,
+                Player::C | Player::D => {}
+            }
+            if rules.match_winner(match_.games_a, match_.games_b).is_some() {
                 next_state_round.set(RoundState::Out);
                 next_state_game.set(GameState::End);
+                return;
+            }
+            scores.a = 0;
+            scores.b = 0;
+        }
+
+        if let Some(scorer) = scorer {
+            match_.server = rules.next_server(match_.server, scorer);
+        }
+        next_state_round.set(RoundState::Countdown);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        headless::headless_app,
+        input_map::{Action, InputMap},
+        match_::{Ball, PauseState, Velocity},
+        rules::ServeRule,
+        MatchMode, MatchSeed, RoundData, LEFT_WALL,
+    };
+
+    /// Updates `app` until `done` holds, at most for a minute of play
+    fn run_until(app: &mut App, done: impl Fn(&World) -> bool) {
+        for _ in 0..64 * 60 {
+            if done(&app.world) {
+                return;
+            }
+            app.update();
+        }
+        panic!("timed out");
+    }
+
+    fn round_state(world: &World) -> RoundState {
+        *world.resource::<State<RoundState>>().get()
+    }
+
+    fn game_state(world: &World) -> GameState {
+        world.resource::<State<GameState>>().get().clone()
+    }
+
+    /// Headless app with a local match started, without input the paddles stay put
+    fn match_app(rules: MatchRules) -> App {
+        let mut app = headless_app();
+        app.update();
+        app.insert_resource(MatchMode::Local);
+        app.insert_resource(rules);
+        app.insert_resource(MatchSeed(Some(1)));
+        app.world
+            .resource_mut::<NextState<GameState>>()
+            .set(GameState::Match);
+        run_until(&mut app, |world| {
+            round_state(world) == RoundState::Countdown
+        });
+        app
+    }
+
+    /// Sends the ball into the goal of Player A
+    fn concede_a(app: &mut App) {
+        let mut ball_query = app
+            .world
+            .query_filtered::<(&mut Transform, &mut Velocity), With<Ball>>();
+        let (mut transform, mut velocity) = ball_query.single_mut(&mut app.world);
+        transform.translation = Vec3::new(LEFT_WALL + 30., 200., 1.);
+        velocity.0 = Vec2::new(-800., 0.);
+    }
+
+    fn play_round(app: &mut App) {
+        run_until(app, |world| round_state(world) == RoundState::In);
+        concede_a(app);
+        run_until(app, |world| round_state(world) == RoundState::Scored);
+    }
+
+    fn games(world: &World) -> (usize, usize) {
+        let match_ = world.resource::<MatchInfo>();
+        (match_.games_a, match_.games_b)
+    }
+
+    fn set_scores(app: &mut App, a: usize, b: usize) {
+        let mut scores = app.world.resource_mut::<Scores>();
+        scores.a = a;
+        scores.b = b;
+    }
+
+    #[test]
+    fn point_with_extra_balls_goes_on_until_all_are_in() {
+        let mut app = match_app(MatchRules {
+            extra_balls: ExtraBalls::Timed,
+            ..default()
+        });
+        let mut ball_query = app
+            .world
+            .query_filtered::<(&mut Transform, &mut Velocity), With<Ball>>();
+
+        // the served ball waits out of the way for the next one
+        run_until(&mut app, |world| round_state(world) == RoundState::In);
+        let (mut transform, mut velocity) = ball_query.single_mut(&mut app.world);
+        transform.translation = Vec3::new(0., 200., 1.);
+        velocity.0 = Vec2::ZERO;
+        run_until(&mut app, |world| {
+            world.resource::<RoundData>().balls_in_play == 2
+        });
+
+        for (mut transform, mut velocity) in ball_query.iter_mut(&mut app.world) {
+            if transform.translation.y == 200. {
+                transform.translation = Vec3::new(LEFT_WALL + 30., 200., 1.);
+                velocity.0 = Vec2::new(-800., 0.);
             } else {
-                // spawn_timed_message(commands, "Must win by 2 points!", 1.0, OnEndScreen);
-                match_.rounds_total += 1;
-                next_state_round.set(RoundState::Countdown);
+                velocity.0 = Vec2::ZERO;
             }
-        } else {
-            next_state_round.set(RoundState::Countdown);
         }
+        run_until(&mut app, |world| world.resource::<Scores>().b == 1);
+        app.update();
+        assert_eq!(round_state(&app.world), RoundState::In);
+        assert_eq!(ball_query.iter(&app.world).count(), 1);
+
+        concede_a(&mut app);
+        run_until(&mut app, |world| round_state(world) != RoundState::In);
+        assert_eq!(round_state(&app.world), RoundState::Scored);
+        assert_eq!(app.world.resource::<Scores>().b, 2);
+    }
+
+    #[test]
+    fn rounds_go_from_countdown_to_play_to_scored() {
+        let mut app = match_app(default());
+
+        run_until(&mut app, |world| round_state(world) == RoundState::In);
+        concede_a(&mut app);
+        run_until(&mut app, |world| round_state(world) != RoundState::In);
+
+        assert_eq!(round_state(&app.world), RoundState::Scored);
+        let scores = app.world.resource::<Scores>();
+        assert_eq!((scores.a, scores.b), (0, 1));
+
+        run_until(&mut app, |world| round_state(world) != RoundState::Scored);
+        assert_eq!(round_state(&app.world), RoundState::Countdown);
+        assert_eq!(app.world.resource::<MatchInfo>().round_count, 1);
+        assert_eq!(game_state(&app.world), GameState::Match);
+    }
+
+    #[test]
+    fn match_ends_when_the_game_is_won_with_a_two_point_lead() {
+        let mut app = match_app(default());
+        play_round(&mut app);
+        run_until(&mut app, |world| round_state(world) != RoundState::Scored);
+        play_round(&mut app);
+
+        run_until(&mut app, |world| round_state(world) != RoundState::Scored);
+
+        assert_eq!(round_state(&app.world), RoundState::Out);
+        assert_eq!(game_state(&app.world), GameState::End);
+        assert_eq!(app.world.resource::<MatchInfo>().round_count, 2);
+        assert_eq!(games(&app.world), (0, 1));
+    }
+
+    #[test]
+    fn match_goes_on_until_someone_leads_by_two() {
+        let mut app = match_app(default());
+        play_round(&mut app);
+        set_scores(&mut app, 1, 1);
+
+        run_until(&mut app, |world| round_state(world) != RoundState::Scored);
+
+        assert_eq!(round_state(&app.world), RoundState::Countdown);
+        assert_eq!(game_state(&app.world), GameState::Match);
+        assert_eq!(games(&app.world), (0, 0));
+
+        // 1 - 2 is still not enough
+        play_round(&mut app);
+        run_until(&mut app, |world| round_state(world) != RoundState::Scored);
+        assert_eq!(round_state(&app.world), RoundState::Countdown);
+
+        // 1 - 3 is
+        play_round(&mut app);
+        run_until(&mut app, |world| round_state(world) != RoundState::Scored);
+        assert_eq!(game_state(&app.world), GameState::End);
+        let scores = app.world.resource::<Scores>();
+        assert_eq!((scores.a, scores.b), (1, 3));
+    }
+
+    #[test]
+    fn best_of_three_starts_a_new_game_until_one_player_wins_two() {
+        let mut app = match_app(MatchRules {
+            best_of: 3,
+            ..default()
+        });
+
+        for _ in 0..2 {
+            play_round(&mut app);
+            run_until(&mut app, |world| round_state(world) != RoundState::Scored);
+        }
+
+        assert_eq!(round_state(&app.world), RoundState::Countdown);
+        assert_eq!(games(&app.world), (0, 1));
+        let scores = app.world.resource::<Scores>();
+        assert_eq!((scores.a, scores.b), (0, 0));
+
+        for _ in 0..2 {
+            play_round(&mut app);
+            run_until(&mut app, |world| round_state(world) != RoundState::Scored);
+        }
+
+        assert_eq!(game_state(&app.world), GameState::End);
+        assert_eq!(games(&app.world), (0, 2));
+    }
+
+    #[test]
+    fn next_round_is_served_by_the_serve_rule() {
+        let mut app = match_app(MatchRules {
+            points_to_win: 5,
+            serve: ServeRule::Loser,
+            ..default()
+        });
+
+        play_round(&mut app);
+        run_until(&mut app, |world| round_state(world) != RoundState::Scored);
+
+        // Player B scored, so Player A serves towards B
+        assert_eq!(app.world.resource::<MatchInfo>().server, Player::A);
+        run_until(&mut app, |world| round_state(world) == RoundState::In);
+        let mut ball_query = app.world.query_filtered::<&Velocity, With<Ball>>();
+        assert!(ball_query.single(&app.world).x > 0.);
+    }
+
+    #[test]
+    fn matches_with_the_same_seed_serve_the_same() {
+        let serves = || {
+            let mut app = match_app(default());
+            play_round(&mut app);
+            run_until(&mut app, |world| {
+                round_state(world) == RoundState::Countdown
+            });
+            let second_serve = app.world.resource::<MatchInfo>().serve;
+            (second_serve, app.world.resource::<MatchInfo>().seed)
+        };
+
+        assert_eq!(serves(), serves());
+        assert_eq!(serves().1, 1);
+    }
+
+    #[test]
+    fn server_can_serve_before_the_countdown_ends() {
+        let mut app = match_app(default());
+        let server = app.world.resource::<MatchInfo>().server;
+        let serve_key =
+            |app: &App, player| app.world.resource::<InputMap>().key(player, Action::Serve);
+
+        let receiver_serve = serve_key(&app, server.opponent());
+        app.world
+            .resource_mut::<ButtonInput<KeyCode>>()
+            .press(receiver_serve);
+        app.update();
+        app.update();
+        assert_eq!(round_state(&app.world), RoundState::Countdown);
+
+        let server_serve = serve_key(&app, server);
+        app.world
+            .resource_mut::<ButtonInput<KeyCode>>()
+            .press(server_serve);
+        app.update();
+        app.update();
+
+        assert_eq!(round_state(&app.world), RoundState::In);
+    }
+
+    #[test]
+    fn goals_are_replayed_until_skipped() {
+        let mut app = match_app(default());
+        play_round(&mut app);
+        app.update();
+        assert!(app.world.contains_resource::<InstantReplay>());
+
+        let mut ball_query = app.world.query_filtered::<&Transform, With<Ball>>();
+        let replayed_at = ball_query.single(&app.world).translation;
+        for _ in 0..16 {
+            app.update();
+        }
+        // the ball goes back to the goal in slow motion
+        assert_ne!(ball_query.single(&app.world).translation, replayed_at);
+        assert_eq!(round_state(&app.world), RoundState::Scored);
+
+        let skip = app
+            .world
+            .resource::<InputMap>()
+            .key(Player::B, Action::Serve);
+        app.world.resource_mut::<ButtonInput<KeyCode>>().press(skip);
+        app.update();
+        assert!(!app.world.contains_resource::<InstantReplay>());
+        assert!(ball_query.single(&app.world).translation.x < LEFT_WALL + 30.);
+
+        run_until(&mut app, |world| round_state(world) != RoundState::Scored);
+        assert_eq!(round_state(&app.world), RoundState::Countdown);
+    }
+
+    #[test]
+    fn pausing_freezes_the_round() {
+        let mut app = match_app(default());
+        run_until(&mut app, |world| round_state(world) == RoundState::In);
+        app.world
+            .resource_mut::<NextState<PauseState>>()
+            .set(PauseState::Paused);
+        app.update();
+
+        let mut ball_query = app.world.query_filtered::<&Transform, With<Ball>>();
+        let paused_at = ball_query.single(&app.world).translation;
+        for _ in 0..64 {
+            app.update();
+        }
+
+        assert_eq!(ball_query.single(&app.world).translation, paused_at);
+        assert_eq!(round_state(&app.world), RoundState::In);
+
+        app.world
+            .resource_mut::<NextState<PauseState>>()
+            .set(PauseState::Running);
+        app.update();
+        app.update();
+        assert_ne!(ball_query.single(&app.world).translation, paused_at);
+    }
+
+    #[test]
+    fn restarting_starts_the_match_over() {
+        let mut app = match_app(default());
+        play_round(&mut app);
+        run_until(&mut app, |world| {
+            round_state(world) == RoundState::Countdown
+        });
+        app.world
+            .resource_mut::<NextState<PauseState>>()
+            .set(PauseState::Paused);
+        app.world
+            .resource_mut::<NextState<GameState>>()
+            .set(GameState::Restarting);
+
+        run_until(&mut app, |world| game_state(world) == GameState::Restarting);
+        run_until(&mut app, |world| {
+            game_state(world) == GameState::Match && round_state(world) == RoundState::Countdown
+        });
+
+        let scores = app.world.resource::<Scores>();
+        assert_eq!((scores.a, scores.b), (0, 0));
+        assert_eq!(app.world.resource::<MatchInfo>().round_count, 0);
+        assert_eq!(
+            *app.world.resource::<State<PauseState>>().get(),
+            PauseState::Running
+        );
+        let mut ball_query = app.world.query_filtered::<Entity, With<Ball>>();
+        assert_eq!(ball_query.iter(&app.world).count(), 1);
+
+        // the new countdown serves the ball
+        run_until(&mut app, |world| round_state(world) == RoundState::In);
+        let mut velocity_query = app.world.query_filtered::<&Velocity, With<Ball>>();
+        assert_ne!(velocity_query.single(&app.world).0, Vec2::ZERO);
     }
 }
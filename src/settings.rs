@@ -0,0 +1,235 @@
+use std::{fs, io::ErrorKind, path::PathBuf};
+
+use bevy::{
+    audio::Volume,
+    prelude::*,
+    window::{PrimaryWindow, WindowMode},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{fps::FpsRoot, input_map::InputMap, match_::Player, rules::MatchRules, PADDLE_SPEED};
+
+pub fn settings_plugin(app: &mut App) {
+    app.add_systems(
+        Update,
+        apply_settings.run_if(resource_exists_and_changed::<Settings>),
+    );
+}
+
+/// User preferences, saved along with the match rules and the input map
+#[derive(Resource, Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(default)]
+pub struct Settings {
+    /// Volume of all sound, from 0 to 1
+    pub master_volume: f32,
+    /// Volume of the collision sounds, from 0 to 1, scaled by master_volume
+    pub effects_volume: f32,
+    /// Whether the FPS counter is shown at startup, F12 still toggles it
+    pub show_fps: bool,
+    pub window_mode: WindowMode,
+    /// Player whose paddle follows the mouse or touch in place of their keys, if any
+    pub pointer_control: Option<Player>,
+    /// Fastest a paddle follows the pointer, no faster than PADDLE_SPEED
+    pub pointer_max_speed: f32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            master_volume: 1.,
+            effects_volume: 1.,
+            show_fps: true,
+            window_mode: WindowMode::Windowed,
+            pointer_control: None,
+            pointer_max_speed: PADDLE_SPEED,
+        }
+    }
+}
+
+/// Contents of the settings file
+#[derive(Serialize, Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct SavedSettings {
+    pub settings: Settings,
+    pub rules: MatchRules,
+    pub input: InputMap,
+}
+
+/// File settings are loaded from and saved to. Without it, as in simulated matches, the
+/// defaults are used and nothing is saved.
+#[derive(Resource)]
+pub struct SettingsFile(pub PathBuf);
+
+impl Default for SettingsFile {
+    fn default() -> Self {
+        let dir = dirs::config_dir().unwrap_or_default().join("pong-bevy");
+        SettingsFile(dir.join("settings.ron"))
+    }
+}
+
+impl SettingsFile {
+    /// Saved settings, or the defaults if there are none or they can't be read
+    pub fn load(&self) -> SavedSettings {
+        let text = match fs::read_to_string(&self.0) {
+            Ok(text) => text,
+            Err(err) if err.kind() == ErrorKind::NotFound => return SavedSettings::default(),
+            Err(err) => {
+                warn!("can't read settings from {}: {}", self.0.display(), err);
+                return SavedSettings::default();
+            }
+        };
+        ron::from_str(&text).unwrap_or_else(|err| {
+            warn!("ignoring corrupt settings in {}: {}", self.0.display(), err);
+            SavedSettings::default()
+        })
+    }
+
+    pub fn save(&self, saved: &SavedSettings) {
+        let text = match ron::ser::to_string_pretty(saved, default()) {
+            Ok(text) => text,
+            Err(err) => {
+                warn!("can't encode settings: {}", err);
+                return;
+            }
+        };
+        let written = match self.0.parent() {
+            Some(dir) => fs::create_dir_all(dir).and_then(|_| fs::write(&self.0, text)),
+            None => fs::write(&self.0, text),
+        };
+        if let Err(err) = written {
+            warn!("can't save settings to {}: {}", self.0.display(), err);
+        }
+    }
+}
+
+pub fn save_settings(
+    settings: Res<Settings>,
+    rules: Res<MatchRules>,
+    input_map: Res<InputMap>,
+    file: Option<Res<SettingsFile>>,
+) {
+    if let Some(file) = file {
+        file.save(&SavedSettings {
+            settings: settings.clone(),
+            rules: *rules,
+            input: input_map.clone(),
+        });
+    }
+}
+
+fn apply_settings(
+    settings: Res<Settings>,
+    mut window_query: Query<&mut Window, With<PrimaryWindow>>,
+    mut fps_query: Query<&mut Visibility, With<FpsRoot>>,
+    global_volume: Option<ResMut<GlobalVolume>>,
+    mut shown_fps: Local<Option<bool>>,
+) {
+    for mut window in &mut window_query {
+        window.mode = settings.window_mode;
+    }
+    if let Some(mut global_volume) = global_volume {
+        global_volume.volume = Volume::new(settings.master_volume);
+    }
+    // leave the counter as F12 left it unless the setting itself changed
+    if *shown_fps != Some(settings.show_fps) {
+        *shown_fps = Some(settings.show_fps);
+        for mut visibility in &mut fps_query {
+            *visibility = if settings.show_fps {
+                Visibility::Visible
+            } else {
+                Visibility::Hidden
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        input_map::{Action, InputMap},
+        match_::Player,
+        rules::ServeRule,
+    };
+
+    /// Directory of its own for a test's files, removed when the test ends, even if it fails
+    struct TestDir(std::path::PathBuf);
+
+    impl TestDir {
+        fn new(test: &str) -> TestDir {
+            let dir =
+                std::env::temp_dir().join(format!("pong-settings-{}-{}", std::process::id(), test));
+            fs::create_dir_all(&dir).unwrap();
+            TestDir(dir)
+        }
+
+        fn file(&self, name: &str) -> SettingsFile {
+            SettingsFile(self.0.join(name))
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn settings_survive_a_save_and_load() {
+        let dir = TestDir::new("save-and-load");
+        let file = dir.file("saved.ron");
+        let saved = SavedSettings {
+            settings: Settings {
+                effects_volume: 0.25,
+                show_fps: false,
+                window_mode: WindowMode::BorderlessFullscreen,
+                ..default()
+            },
+            rules: MatchRules {
+                points_to_win: 11,
+                serve: ServeRule::Alternate,
+                ..default()
+            },
+            input: {
+                let mut input_map = InputMap::default();
+                input_map
+                    .rebind(Player::B, Action::Serve, KeyCode::Numpad0)
+                    .unwrap();
+                input_map
+            },
+        };
+
+        file.save(&saved);
+        let loaded = file.load();
+
+        assert_eq!(loaded.settings, saved.settings);
+        assert_eq!(loaded.rules, saved.rules);
+        assert_eq!(loaded.input, saved.input);
+    }
+
+    #[test]
+    fn missing_or_corrupt_files_load_the_defaults() {
+        let dir = TestDir::new("missing-or-corrupt");
+        let missing = dir.file("missing.ron");
+        assert_eq!(missing.load().settings, Settings::default());
+
+        let corrupt = dir.file("corrupt.ron");
+        fs::write(&corrupt.0, "(settings: (master_volume: \"loud\"").unwrap();
+        assert_eq!(corrupt.load().settings, Settings::default());
+        assert_eq!(corrupt.load().rules, MatchRules::default());
+    }
+
+    #[test]
+    fn fields_missing_from_the_file_keep_their_defaults() {
+        let dir = TestDir::new("partial");
+        let file = dir.file("partial.ron");
+        fs::write(&file.0, "(settings: (effects_volume: 0.5))").unwrap();
+
+        let loaded = file.load();
+
+        assert_eq!(loaded.settings.effects_volume, 0.5);
+        assert_eq!(loaded.settings.show_fps, Settings::default().show_fps);
+        assert_eq!(loaded.input, InputMap::default());
+        assert_eq!(loaded.rules, MatchRules::default());
+    }
+}
@@ -9,39 +9,59 @@ use bevy::{
 use bevy_vector_shapes::prelude::*;
 
 use crate::{
-    GameState, GameTimer, BALL_COLOR, BALL_RADIUS, BALL_START_POSITION, BALL_START_SPEED,
-    BOTTOM_WALL, GAP_BETWEEN_PADDLE_AND_GOAL, LEFT_WALL, PADDLE_A_START_POSITION,
-    PADDLE_B_START_POSITION, PADDLE_COLOR, PADDLE_SIZE, PADDLE_SPEED, RIGHT_WALL, ROUNDS_TOTAL,
-    SCORE_A_POSITION, SCORE_B_POSITION, SCORE_FONT_SIZE, TEXT_COLOR, TOP_WALL, WALL_THICKNESS,
+    ai::AiDifficulty, match_::Player, profiles::ProfilesFile, settings::SettingsFile, GameState,
+    GameTimer, BALL_COLOR, BALL_RADIUS, BALL_START_POSITION, BALL_START_SPEED, BOTTOM_WALL,
+    GAP_BETWEEN_PADDLE_AND_GOAL, LEFT_WALL, PADDLE_A_START_POSITION, PADDLE_B_START_POSITION,
+    PADDLE_COLOR, PADDLE_SIZE, PADDLE_SPEED, RIGHT_WALL, SCORE_A_POSITION, SCORE_B_POSITION,
+    SCORE_FONT_SIZE, TEXT_COLOR, TOP_WALL, WALL_THICKNESS,
 };
 
 pub fn setup(
     mut commands: Commands,
-    asset_server: Res<AssetServer>,
     mut next_state: ResMut<NextState<GameState>>,
+    settings_file: Option<Res<SettingsFile>>,
+    profiles_file: Option<Res<ProfilesFile>>,
 ) {
-    commands.spawn(Camera2dBundle::default());
+    let saved = settings_file.map(|file| file.load()).unwrap_or_default();
 
-    let wall_collision_sound = asset_server.load("sounds/breakout_collision.ogg");
-    let paddle_collision_sound = asset_server.load("sounds/med_shoot.wav");
-    let goal_collision_sound = asset_server.load("sounds/jump.wav");
-    commands.insert_resource(CollisionSounds {
-        wall: wall_collision_sound,
-        paddle: paddle_collision_sound,
-        goal: goal_collision_sound,
-    });
     commands.insert_resource(Scores { a: 0, b: 0 });
     commands.insert_resource(MatchInfo {
         round_count: 0,
-        rounds_total: ROUNDS_TOTAL,
+        games_a: 0,
+        games_b: 0,
+        server: Player::B,
+        serve: Vec2::ZERO,
+        seed: 0,
     });
+    commands.insert_resource(MatchSeed(None));
+    commands.insert_resource(saved.settings);
+    commands.insert_resource(saved.rules);
+    commands.insert_resource(saved.input);
+    commands.insert_resource(profiles_file.map(|file| file.load()).unwrap_or_default());
     commands.insert_resource(RoundData {
         paddle_hit_count: 0,
+        balls_in_play: 0,
     });
+    commands.insert_resource(MatchMode::Local);
 
     next_state.set(GameState::Menu);
 }
 
+pub fn setup_camera(mut commands: Commands) {
+    commands.spawn(Camera2dBundle::default());
+}
+
+pub fn load_collision_sounds(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let wall_collision_sound = asset_server.load("sounds/breakout_collision.ogg");
+    let paddle_collision_sound = asset_server.load("sounds/med_shoot.wav");
+    let goal_collision_sound = asset_server.load("sounds/jump.wav");
+    commands.insert_resource(CollisionSounds {
+        wall: wall_collision_sound,
+        paddle: paddle_collision_sound,
+        goal: goal_collision_sound,
+    });
+}
+
 #[derive(Event)]
 pub enum CollisionEvent {
     Wall,
@@ -49,15 +69,14 @@ pub enum CollisionEvent {
     Goal,
 }
 
-#[derive(Event)]
+#[derive(Event, Clone, Copy, PartialEq, Eq, Debug)]
 pub enum ScoreEvent {
     A,
     B,
 }
 
 #[derive(Resource)]
-pub struct tst
-CollisionSounds {
+pub struct CollisionSounds {
     pub wall: Handle<AudioSource>,
     pub paddle: Handle<AudioSource>,
     pub goal: Handle<AudioSource>,
@@ -72,12 +91,87 @@ pub struct Scores {
 #[derive(Resource)]
 pub struct MatchInfo {
     pub round_count: usize,
-    pub rounds_total: usize,
+    /// Games won so far, the points of the current game are in `Scores`
+    pub games_a: usize,
+    pub games_b: usize,
+    /// Player serving the next round, the ball starts towards the other one
+    pub server: Player,
+    /// Velocity the ball is served with when the next round starts
+    pub serve: Vec2,
+    /// Seed of the serve angles of this match
+    pub seed: u64,
 }
 
+/// Seed of the serve angles of the next matches, None for a new random one each match
+#[derive(Resource)]
+pub struct MatchSeed(pub Option<u64>);
+
 #[derive(Resource)]
 pub struct RoundData {
     pub paddle_hit_count: usize,
+    /// Balls that were not in a goal after the last tick, the point is over at none
+    pub balls_in_play: usize,
+}
+
+/// Who controls the paddles in the next match
+#[derive(Resource, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MatchMode {
+    Local,
+    VsAi(AiDifficulty),
+    /// Both paddles computer-controlled, Player A first, for simulated matches
+    AiVsAi(AiDifficulty, AiDifficulty),
+    /// Player A on this machine, Player B steered by the connected client
+    NetworkHost,
+    /// Player B on this machine, everything else mirrored from the host
+    NetworkClient,
+    /// Both paddles replaying the inputs of a recorded match
+    Replay,
+    /// Four players on this machine, one on each side, each out after losing their lives
+    FourPlayer,
+    /// Two paddles a side, steered by the players of the `Seat`s. Each seat is played on this
+    /// machine, or by the AI at the difficulty set for it, by `Player::index`.
+    Doubles([Option<AiDifficulty>; 4]),
+}
+
+impl MatchMode {
+    /// Players with a paddle in the match
+    pub fn players(&self) -> &'static [Player] {
+        match self {
+            MatchMode::FourPlayer | MatchMode::Doubles(_) => &Player::ALL,
+            _ => &[Player::A, Player::B],
+        }
+    }
+
+    /// Sides of the arena that score, teams of two in doubles
+    pub fn sides(&self) -> &'static [Player] {
+        match self {
+            MatchMode::FourPlayer => &Player::ALL,
+            _ => &[Player::A, Player::B],
+        }
+    }
+
+    /// Name of the side of `player` in messages and on the scoreboard
+    pub fn side_name(&self, player: Player) -> String {
+        match (self, player) {
+            (MatchMode::Doubles(_), Player::A) => "Team A & C".to_string(),
+            (MatchMode::Doubles(_), Player::B) => "Team B & D".to_string(),
+            _ => format!("Player {:?}", player),
+        }
+    }
+
+    /// Whether this machine simulates the match and decides round transitions
+    pub fn is_authority(&self) -> bool {
+        *self != MatchMode::NetworkClient
+    }
+
+    /// Whether the match is played against a peer over the network
+    pub fn is_network(&self) -> bool {
+        matches!(self, MatchMode::NetworkHost | MatchMode::NetworkClient)
+    }
+}
+
+pub fn is_match_authority(match_mode: Res<MatchMode>) -> bool {
+    match_mode.is_authority()
 }
 
 pub fn spawn_timed_message(
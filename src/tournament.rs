@@ -0,0 +1,483 @@
+use std::{fs, io::ErrorKind, path::PathBuf};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    match_::{match_winner, Player},
+    GameState, MatchInfo, Scores,
+};
+
+/// Fewest players a tournament can start with
+pub const MIN_PLAYERS: usize = 4;
+
+/// Most players a tournament can start with
+pub const MAX_PLAYERS: usize = 32;
+
+pub fn tournament_plugin(app: &mut App) {
+    app.add_systems(Startup, load_tournament).add_systems(
+        OnEnter(GameState::End),
+        record_tournament_match.run_if(resource_exists::<TournamentMatch>),
+    );
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum BracketFormat {
+    #[default]
+    SingleElimination,
+    /// Players are out after their second loss, the last one left in the losers bracket
+    /// meets the winners bracket champion in a single grand final
+    DoubleElimination,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Bracket {
+    #[default]
+    Winners,
+    Losers,
+    GrandFinal,
+}
+
+/// Where one of the two players of a bracket match comes from
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Slot {
+    /// The player entered at this index
+    Seed(usize),
+    /// Nobody, the other player goes through without playing
+    Bye,
+    /// The winner of the match at this index
+    Winner(usize),
+    /// The loser of the match at this index
+    Loser(usize),
+}
+
+/// Who fills a slot once the matches it depends on are decided
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Entrant {
+    Player(usize),
+    Bye,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct BracketMatch {
+    pub bracket: Bracket,
+    /// Round within its bracket, from 0
+    pub round: usize,
+    /// Player A and Player B of the match
+    pub slots: [Slot; 2],
+    pub winner: Option<Player>,
+}
+
+/// A tournament in progress, or finished until a new one is started
+#[derive(Resource, Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Tournament {
+    pub format: BracketFormat,
+    /// Names, in seeding order
+    pub players: Vec<String>,
+    /// Every match of the brackets, each after the matches its players come from
+    pub matches: Vec<BracketMatch>,
+}
+
+impl Tournament {
+    /// Brackets for `players`, seeded in the order given. The top seeds get the byes when
+    /// there are fewer players than places. None if there are too few or too many players.
+    pub fn new(players: Vec<String>, format: BracketFormat) -> Option<Tournament> {
+        if !(MIN_PLAYERS..=MAX_PLAYERS).contains(&players.len()) {
+            return None;
+        }
+        let places = players.len().next_power_of_two();
+        let rounds = places.trailing_zeros() as usize;
+        let seed = |seed: usize| {
+            if seed < players.len() {
+                Slot::Seed(seed)
+            } else {
+                Slot::Bye
+            }
+        };
+
+        let mut matches = Vec::new();
+        let add = |matches: &mut Vec<BracketMatch>, bracket, round, slots| {
+            matches.push(BracketMatch {
+                bracket,
+                round,
+                slots,
+                winner: None,
+            });
+            matches.len() - 1
+        };
+
+        let order = seeding_order(places);
+        let mut winners: Vec<usize> = order
+            .chunks(2)
+            .map(|pair| {
+                add(
+                    &mut matches,
+                    Bracket::Winners,
+                    0,
+                    [seed(pair[0]), seed(pair[1])],
+                )
+            })
+            .collect();
+        let mut losers: Vec<usize> = Vec::new();
+
+        for round in 1..=rounds {
+            let previous = winners.clone();
+            if round < rounds {
+                winners = previous
+                    .chunks(2)
+                    .map(|pair| {
+                        let slots = [Slot::Winner(pair[0]), Slot::Winner(pair[1])];
+                        add(&mut matches, Bracket::Winners, round, slots)
+                    })
+                    .collect();
+            }
+            if format == BracketFormat::SingleElimination {
+                continue;
+            }
+
+            if round == 1 {
+                // the first losers round pairs the players out of the first winners round
+                losers = previous
+                    .chunks(2)
+                    .map(|pair| {
+                        let slots = [Slot::Loser(pair[0]), Slot::Loser(pair[1])];
+                        add(&mut matches, Bracket::Losers, 0, slots)
+                    })
+                    .collect();
+                continue;
+            }
+            // players dropping in from the winners bracket meet the survivors, in reverse so
+            // the first rematches come as late as possible
+            let losers_round = 2 * round - 3;
+            losers = losers
+                .iter()
+                .zip(previous.iter().rev())
+                .map(|(&survivor, &dropped)| {
+                    let slots = [Slot::Winner(survivor), Slot::Loser(dropped)];
+                    add(&mut matches, Bracket::Losers, losers_round, slots)
+                })
+                .collect();
+            if round < rounds {
+                losers = losers
+                    .chunks(2)
+                    .map(|pair| {
+                        let slots = [Slot::Winner(pair[0]), Slot::Winner(pair[1])];
+                        add(&mut matches, Bracket::Losers, losers_round + 1, slots)
+                    })
+                    .collect();
+            }
+        }
+
+        if format == BracketFormat::DoubleElimination {
+            let final_slots = [Slot::Winner(winners[0]), Slot::Winner(losers[0])];
+            add(&mut matches, Bracket::GrandFinal, 0, final_slots);
+        }
+
+        Some(Tournament {
+            format,
+            players,
+            matches,
+        })
+    }
+
+    /// Whether the brackets can be played out: there is a last match, every seed is one of the
+    /// players and every match only depends on matches before it. Anything read back from a
+    /// file has to be, or looking up the players of a match would fail.
+    pub fn is_consistent(&self) -> bool {
+        !self.matches.is_empty()
+            && self.matches.iter().enumerate().all(|(index, match_)| {
+                match_.slots.iter().all(|&slot| match slot {
+                    Slot::Seed(seed) => seed < self.players.len(),
+                    Slot::Bye => true,
+                    Slot::Winner(from) | Slot::Loser(from) => from < index,
+                })
+            })
+    }
+
+    /// Who fills `slot`, None until the matches it depends on are decided
+    pub fn entrant(&self, slot: Slot) -> Option<Entrant> {
+        match slot {
+            Slot::Seed(seed) => Some(Entrant::Player(seed)),
+            Slot::Bye => Some(Entrant::Bye),
+            Slot::Winner(index) => self.outcome(index).map(|(winner, _)| winner),
+            Slot::Loser(index) => self.outcome(index).map(|(_, loser)| loser),
+        }
+    }
+
+    /// Winner and loser of the match at `index`, a player against a bye wins without playing
+    pub fn outcome(&self, index: usize) -> Option<(Entrant, Entrant)> {
+        let match_ = &self.matches[index];
+        let a = self.entrant(match_.slots[0])?;
+        let b = self.entrant(match_.slots[1])?;
+        match (a, b, match_.winner) {
+            (Entrant::Bye, other, _) | (other, Entrant::Bye, _) => Some((other, Entrant::Bye)),
+            (_, _, Some(Player::A)) => Some((a, b)),
+            (_, _, Some(Player::B)) => Some((b, a)),
+            // tournament matches are played by two players
+            (_, _, Some(Player::C | Player::D) | None) => None,
+        }
+    }
+
+    /// Names of Player A and B of the match at `index`, if both are known and it needs playing
+    pub fn opponents(&self, index: usize) -> Option<(&str, &str)> {
+        let match_ = &self.matches[index];
+        if match_.winner.is_some() {
+            return None;
+        }
+        match (
+            self.entrant(match_.slots[0])?,
+            self.entrant(match_.slots[1])?,
+        ) {
+            (Entrant::Player(a), Entrant::Player(b)) => Some((&self.players[a], &self.players[b])),
+            _ => None,
+        }
+    }
+
+    /// Index of the next match to play, None once the tournament is over
+    pub fn next_match(&self) -> Option<usize> {
+        (0..self.matches.len()).find(|&index| self.opponents(index).is_some())
+    }
+
+    pub fn record_winner(&mut self, index: usize, winner: Player) {
+        self.matches[index].winner = Some(winner);
+    }
+
+    /// Winner of the last match, once it is decided
+    pub fn champion(&self) -> Option<&str> {
+        match self.outcome(self.matches.len() - 1)? {
+            (Entrant::Player(champion), _) => Some(&self.players[champion]),
+            (Entrant::Bye, _) => None,
+        }
+    }
+}
+
+// Seeds in bracket order for `places` places, each pair is a first round match and seeds
+// meet the best seeds they can only as late as possible
+fn seeding_order(places: usize) -> Vec<usize> {
+    let mut order = vec![0];
+    while order.len() < places {
+        let size = order.len() * 2;
+        order = order
+            .iter()
+            .flat_map(|&seed| [seed, size - 1 - seed])
+            .collect();
+    }
+    order
+}
+
+/// Index of the tournament match being played
+#[derive(Resource)]
+pub struct TournamentMatch(pub usize);
+
+/// File the tournament is saved to after every change, so it carries on after a restart
+#[derive(Resource)]
+pub struct TournamentFile(pub PathBuf);
+
+impl Default for TournamentFile {
+    fn default() -> Self {
+        let dir = dirs::data_dir().unwrap_or_default().join("pong-bevy");
+        TournamentFile(dir.join("tournament.ron"))
+    }
+}
+
+impl TournamentFile {
+    /// Saved tournament, if there is one that can be read
+    pub fn load(&self) -> Option<Tournament> {
+        let text = match fs::read_to_string(&self.0) {
+            Ok(text) => text,
+            Err(err) if err.kind() == ErrorKind::NotFound => return None,
+            Err(err) => {
+                warn!("can't read tournament from {}: {}", self.0.display(), err);
+                return None;
+            }
+        };
+        let corrupt = |reason: &dyn std::fmt::Display| {
+            warn!(
+                "ignoring corrupt tournament in {}: {}",
+                self.0.display(),
+                reason
+            )
+        };
+        let tournament: Tournament = match ron::from_str(&text) {
+            Ok(tournament) => tournament,
+            Err(err) => {
+                corrupt(&err);
+                return None;
+            }
+        };
+        if !tournament.is_consistent() {
+            corrupt(&"the brackets don't add up");
+            return None;
+        }
+        Some(tournament)
+    }
+
+    pub fn save(&self, tournament: &Tournament) {
+        let text = match ron::ser::to_string_pretty(tournament, default()) {
+            Ok(text) => text,
+            Err(err) => {
+                warn!("can't encode tournament: {}", err);
+                return;
+            }
+        };
+        let written = match self.0.parent() {
+            Some(dir) => fs::create_dir_all(dir).and_then(|_| fs::write(&self.0, text)),
+            None => fs::write(&self.0, text),
+        };
+        if let Err(err) = written {
+            warn!("can't save tournament to {}: {}", self.0.display(), err);
+        }
+    }
+
+    /// Forgets the saved tournament
+    pub fn clear(&self) {
+        match fs::remove_file(&self.0) {
+            Err(err) if err.kind() != ErrorKind::NotFound => {
+                warn!("can't remove tournament {}: {}", self.0.display(), err);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn load_tournament(mut commands: Commands, file: Option<Res<TournamentFile>>) {
+    if let Some(tournament) = file.and_then(|file| file.load()) {
+        commands.insert_resource(tournament);
+    }
+}
+
+/// Moves the winner of the match just ended on in the bracket
+fn record_tournament_match(
+    playing: Res<TournamentMatch>,
+    mut tournament: ResMut<Tournament>,
+    file: Option<Res<TournamentFile>>,
+    scores: Res<Scores>,
+    match_: Res<MatchInfo>,
+) {
+    tournament.record_winner(playing.0, match_winner(&scores, &match_));
+    if let Some(file) = file {
+        file.save(&tournament);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn players(count: usize) -> Vec<String> {
+        (1..=count).map(|n| format!("P{}", n)).collect()
+    }
+
+    // Plays the tournament out, the better seed winning every match
+    fn play_out(tournament: &mut Tournament) -> usize {
+        let mut played = 0;
+        while let Some(index) = tournament.next_match() {
+            let (a, b) = tournament.opponents(index).unwrap();
+            let seed = |name: &str| tournament.players.iter().position(|p| p == name);
+            let winner = if seed(a) < seed(b) {
+                Player::A
+            } else {
+                Player::B
+            };
+            tournament.record_winner(index, winner);
+            played += 1;
+        }
+        played
+    }
+
+    #[test]
+    fn player_counts_are_limited() {
+        let single = BracketFormat::SingleElimination;
+        assert!(Tournament::new(players(3), single).is_none());
+        assert!(Tournament::new(players(4), single).is_some());
+        assert!(Tournament::new(players(32), single).is_some());
+        assert!(Tournament::new(players(33), single).is_none());
+    }
+
+    #[test]
+    fn top_seeds_get_the_byes() {
+        let tournament = Tournament::new(players(6), BracketFormat::SingleElimination).unwrap();
+
+        let byes: Vec<&str> = (0..4)
+            .filter_map(|index| match tournament.outcome(index) {
+                Some((Entrant::Player(seed), Entrant::Bye)) => Some(&*tournament.players[seed]),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(byes.len(), 2);
+        assert!(byes.contains(&"P1") && byes.contains(&"P2"));
+        assert_eq!(
+            tournament.opponents(tournament.next_match().unwrap()),
+            Some(("P4", "P5"))
+        );
+    }
+
+    #[test]
+    fn single_elimination_plays_one_match_less_than_players() {
+        for count in [4, 5, 8, 13, 32] {
+            let mut tournament =
+                Tournament::new(players(count), BracketFormat::SingleElimination).unwrap();
+            assert_eq!(play_out(&mut tournament), count - 1);
+            assert_eq!(tournament.champion(), Some("P1"));
+        }
+    }
+
+    #[test]
+    fn double_elimination_knocks_players_out_after_two_losses() {
+        for count in [4, 7, 8, 16, 32] {
+            let mut tournament =
+                Tournament::new(players(count), BracketFormat::DoubleElimination).unwrap();
+            // everyone but the champion and the grand final loser is out after two losses
+            assert_eq!(play_out(&mut tournament), 2 * count - 2);
+            assert_eq!(tournament.champion(), Some("P1"));
+        }
+    }
+
+    #[test]
+    fn losers_bracket_champion_reaches_the_grand_final() {
+        let mut tournament = Tournament::new(players(4), BracketFormat::DoubleElimination).unwrap();
+        // P4 upsets P1 in the first round, then P1 wins the losers bracket
+        while let Some(index) = tournament.next_match() {
+            let winner = match tournament.opponents(index).unwrap() {
+                ("P1", "P4") => Player::B,
+                (_, "P1") => Player::B,
+                ("P1", _) => Player::A,
+                _ => Player::A,
+            };
+            tournament.record_winner(index, winner);
+        }
+
+        let grand_final = tournament.matches.last().unwrap();
+        assert_eq!(grand_final.bracket, Bracket::GrandFinal);
+        assert_eq!(tournament.champion(), Some("P1"));
+    }
+
+    #[test]
+    fn inconsistent_brackets_are_caught() {
+        for format in [
+            BracketFormat::SingleElimination,
+            BracketFormat::DoubleElimination,
+        ] {
+            let tournament = Tournament::new(players(5), format).unwrap();
+            assert!(tournament.is_consistent());
+
+            let mut empty = tournament.clone();
+            empty.matches.clear();
+            assert!(!empty.is_consistent());
+
+            let mut unknown_seed = tournament.clone();
+            unknown_seed.matches[0].slots[0] = Slot::Seed(5);
+            assert!(!unknown_seed.is_consistent());
+
+            // a match waiting on itself, or on a later one, would never be decided
+            let mut cycle = tournament.clone();
+            cycle.matches[0].slots[1] = Slot::Winner(0);
+            assert!(!cycle.is_consistent());
+            let mut later = tournament.clone();
+            let last = later.matches.len() - 1;
+            later.matches[1].slots[0] = Slot::Loser(last);
+            assert!(!later.is_consistent());
+        }
+    }
+}